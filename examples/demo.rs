@@ -17,7 +17,7 @@ mod app {
             timer::{CounterUs, DelayUs},
         },
         pac, Angle, Button, Direction, Led, Mikoto, MikotoPeripherals, MikotoWheels, Mpu6050,
-        Vl53l1x, YawPitchRoll,
+        TurnStatus, Vl53l1x, YawPitchRoll,
     };
     use mpu6050_dmp::yaw_pitch_roll::YawPitchRoll as YPR;
 
@@ -156,13 +156,9 @@ mod app {
         let counter: &mut CounterUs<TIM2> = ctx.local.counter;
         let delay: &mut DelayUs<TIM4> = ctx.local.delay;
 
-        enum TurnStep {
-            Left,
-            Right,
-        }
-
-        let mut turn = TurnStep::Left;
-        let mut turn_direction = Direction::Forward;
+        // Yaw (deg) to turn to in order: left a quarter turn twice, then back right
+        const TURNS: [f32; 4] = [90.0, 179.0, 90.0, 1.0];
+        let mut turn = 0;
 
         enum DistanceStep {
             First,
@@ -203,13 +199,12 @@ mod app {
 
         let mut distance_step = DistanceStep::First;
 
-        let mut c_started = false;
-        let mut c_start_t = 0;
-
-        let mut start_turn = true;
-
         // The idle loop
         loop {
+            // Time (us) since the last loop
+            let dt = counter.now().ticks();
+            counter.start(1.secs()).unwrap();
+
             let mut gyro_reading = gyro.read();
             // Gyro is mounted upside down, so directions are reversed.
             gyro_reading = YawPitchRoll::from(YPR {
@@ -228,95 +223,47 @@ mod app {
                 Task::WaitForButton => {}
                 Task::TofDrive => {
                     mikoto
-                        .drive_straight(gyro_reading.yaw, Angle::new(0.0), Direction::Forward, 100)
+                        .drive_straight(
+                            -gyro_reading.yaw,
+                            Angle::new(0.0),
+                            Direction::Forward,
+                            100,
+                            dt,
+                        )
                         .unwrap();
                     let current_distance = tof.read(i2c, delay);
-                    if !c_started && current_distance <= distance_step.target_dist() {
+                    if current_distance <= distance_step.target_dist() {
                         mikoto.stop().unwrap();
                         distance_step.next();
                         *t = Task::WaitForButton;
                     }
                 }
-                Task::PreciseTurn => match turn {
-                    TurnStep::Left => {
-                        if start_turn {
-                            mikoto.drive(Direction::Left, 5).unwrap();
-                            start_turn = false;
-                        }
-
-                        let desired_angle = match turn_direction {
-                            Direction::Forward => Angle::new(-90.0),
-                            Direction::Left => Angle::new(-179.0),
-                            _ => panic!("invalid turn direction"),
-                        };
-
-                        if !c_started && gyro_reading.yaw.to_degrees() <= desired_angle {
-                            defmt::debug!("Left turn stop");
-                            mikoto.stop().unwrap();
-                            c_started = true;
-                            counter.start(3.secs()).unwrap();
-                            c_start_t = counter.now().ticks();
-                        } else if c_started && (counter.now().ticks() - c_start_t) > 1_000_000 * 2 {
-                            c_started = false;
-                            counter.cancel().unwrap();
-                            match turn_direction {
-                                Direction::Forward => {
-                                    turn_direction = Direction::Left;
-                                    turn = TurnStep::Left;
-                                }
-                                Direction::Left => {
-                                    turn_direction = Direction::Backward;
-                                    turn = TurnStep::Right;
-                                }
-                                _ => {}
+                Task::PreciseTurn => {
+                    let target: Angle<Degrees> = Angle::new(TURNS[turn]);
+                    // Yaw is reversed above, but turn_to needs yaw increasing to the left
+                    match mikoto
+                        .turn_to(-gyro_reading.yaw, target.to_radians(), dt)
+                        .unwrap()
+                    {
+                        TurnStatus::InProgress => {}
+                        status => {
+                            defmt::debug!("Turn to {}: {}", target, status);
+                            turn = (turn + 1) % TURNS.len();
+                            if turn == 0 {
+                                *t = Task::WaitForButton;
                             }
-                            start_turn = true;
                         }
                     }
-
-                    TurnStep::Right => {
-                        if start_turn {
-                            mikoto.drive(Direction::Right, 5).unwrap();
-                            start_turn = false;
-                        }
-                        let desired_angle: Angle<Degrees> = match turn_direction {
-                            Direction::Backward => Angle::new(-90.0),
-                            Direction::Left => Angle::new(-1.0),
-                            _ => panic!("invalid turn direction"),
-                        };
-
-                        if !c_started
-                            && libm::fabsf(gyro_reading.yaw.to_degrees().value())
-                                <= libm::fabsf(desired_angle.value())
-                        {
-                            defmt::debug!("Right turn stop");
-                            mikoto.stop().unwrap();
-                            c_started = true;
-                            counter.start(3.secs()).unwrap();
-                            c_start_t = counter.now().ticks();
-                        } else if c_started && (counter.now().ticks() - c_start_t) > 1_000_000 * 2 {
-                            mikoto.stop().unwrap();
-                            c_started = false;
-                            counter.cancel().unwrap();
-                            match turn_direction {
-                                Direction::Backward => {
-                                    turn_direction = Direction::Left;
-                                    turn = TurnStep::Right;
-                                }
-                                Direction::Left => {
-                                    turn_direction = Direction::Forward;
-                                    turn = TurnStep::Left;
-                                    *t = Task::WaitForButton;
-                                }
-                                _ => {}
-                            }
-                            start_turn = true;
-                        }
-                    }
-                },
+                }
                 Task::Drive => {
                     mikoto
-                        .drive_straight(gyro_reading.yaw, Angle::new(0.0), Direction::Forward, 100)
+                        .drive_straight(
+                            -gyro_reading.yaw,
+                            Angle::new(0.0),
+                            Direction::Forward,
+                            100,
+                            dt,
+                        )
                         .unwrap();
                 }
             });
@@ -0,0 +1,50 @@
+#![no_main]
+#![no_std]
+
+use cortex_m::peripheral::Peripherals;
+use cortex_m_rt::entry;
+use defmt_rtt as _;
+use panic_probe as _;
+
+use mikoto_bot::{
+    hal::{prelude::*, qei::QeiExt},
+    pac, Encoder,
+};
+
+#[entry]
+fn main() -> ! {
+    // The Stm32 peripherals
+    let dp = pac::Peripherals::take().unwrap();
+    // The Cortex-m peripherals
+    let _core = Peripherals::take().unwrap();
+
+    // Constrain clock registers
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.use_hse(8.MHz()).freeze();
+
+    let mut delay = dp.TIM5.delay_us(&clocks);
+
+    let gpiob = dp.GPIOB.split();
+
+    // Quadrature encoder on TIM4, channels A and B on PB6 and PB7
+    let qei = dp
+        .TIM4
+        .qei((gpiob.pb6.into_alternate(), gpiob.pb7.into_alternate()));
+    // 12 counts per motor revolution through a 48:1 gearbox, on a 70mm wheel
+    let mut encoder = Encoder::new(qei, 12 * 48, 70.0 * core::f32::consts::PI);
+
+    // Time (us) between updates
+    const TICK: u32 = 100_000;
+
+    defmt::info!("init");
+    loop {
+        delay.delay_us(TICK);
+        let velocity = encoder.update(TICK);
+        defmt::info!(
+            "Counts: {}, distance: {}mm, velocity: {}mm/s",
+            encoder.counts(),
+            encoder.distance(),
+            velocity
+        );
+    }
+}
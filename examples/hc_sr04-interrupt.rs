@@ -0,0 +1,113 @@
+#![no_main]
+#![no_std]
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::Peripherals;
+use cortex_m_rt::entry;
+use defmt_rtt as _;
+use panic_probe as _;
+
+use mikoto_bot::{
+    distance_unit::PulseDuration,
+    hal::{interrupt, nb, prelude::*},
+    hc_sr04::Distance,
+    pac,
+    pac::TIM2,
+    EchoClock, EchoTiming, HcSr04, Led, Ultrasonic,
+};
+
+type Sensor = HcSr04<'A', 9, 'A', 8>;
+/// The sensor and the clock timing its echo, shared with the interrupt handler
+type Timed = (Sensor, EchoClock<TIM2>);
+
+static ULTRASONIC: Mutex<RefCell<Option<Timed>>> = Mutex::new(RefCell::new(None));
+
+#[entry]
+fn main() -> ! {
+    // The Stm32 peripherals
+    let mut dp = pac::Peripherals::take().unwrap();
+    // The Cortex-m peripherals
+    let _core = Peripherals::take().unwrap();
+
+    // Constrain clock registers
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.use_hse(8.MHz()).freeze();
+
+    let mut syscfg = dp.SYSCFG.constrain();
+
+    let mut delay = dp.TIM5.delay_us(&clocks);
+    // Times the echo, wrapping every 100ms
+    let clock = EchoClock::new(dp.TIM2.counter_us(&clocks), 100_000).unwrap();
+
+    let gpioa = dp.GPIOA.split();
+
+    // Configure ultrasonic sensor and enable the echo pin's external interrupt
+    let trig = gpioa.pa9;
+    let echo = gpioa.pa8;
+    let mut ultrasonic: Sensor = HcSr04::new(trig, echo);
+    ultrasonic.enable_interrupt(&mut syscfg, &mut dp.EXTI);
+
+    cortex_m::interrupt::free(|cs| {
+        ULTRASONIC.borrow(cs).replace(Some((ultrasonic, clock)));
+    });
+
+    // Configure PA5 (LD2 - User LED) as an output
+    let mut led = Led::new(gpioa.pa5);
+
+    // Distance at which the LED will turn on.
+    let led_distance = Distance::new(25_u32);
+
+    // Enable the external interrupt (PA8)
+    unsafe {
+        cortex_m::peripheral::NVIC::unmask(pac::Interrupt::EXTI9_5);
+    }
+
+    defmt::info!("init");
+    loop {
+        let triggered = cortex_m::interrupt::free(|cs| {
+            let mut ultrasonic = ULTRASONIC.borrow(cs).borrow_mut();
+            let (ultrasonic, clock) = ultrasonic.as_mut().unwrap();
+            ultrasonic.trigger(clock)
+        });
+        if triggered.is_err() {
+            // Previous measurement cycle hasn't ended yet
+            continue;
+        }
+
+        // The echo is timed in the background, so the CPU is free until the result is needed
+        delay.delay_ms(60_u32);
+
+        let pulse = cortex_m::interrupt::free(|cs| {
+            let mut ultrasonic = ULTRASONIC.borrow(cs).borrow_mut();
+            let (ultrasonic, clock) = ultrasonic.as_mut().unwrap();
+            ultrasonic.poll(clock)
+        });
+
+        match pulse {
+            Ok(pulse) => {
+                let distance = Distance::<PulseDuration>::new(pulse).as_cm();
+                defmt::info!("Distance: {}", distance);
+
+                // turn the LED on if closer than 25cm, off if further than 25cm
+                if (!led.is_on() && distance < led_distance)
+                    || (led.is_on() && distance >= led_distance)
+                {
+                    led.toggle();
+                }
+            }
+            Err(nb::Error::WouldBlock) => defmt::warn!("Measurement still in progress"),
+            Err(nb::Error::Other(e)) => defmt::warn!("Measurement failed! Error: {}", e),
+        }
+    }
+}
+
+#[interrupt]
+fn EXTI9_5() {
+    cortex_m::interrupt::free(|cs| {
+        let mut ultrasonic = ULTRASONIC.borrow(cs).borrow_mut();
+        let (ultrasonic, clock) = ultrasonic.as_mut().unwrap();
+        ultrasonic.on_echo_edge(clock);
+    });
+}
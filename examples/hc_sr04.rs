@@ -6,7 +6,9 @@ use cortex_m_rt::entry;
 use defmt_rtt as _;
 use panic_probe as _;
 
-use mikoto_bot::{hal::prelude::*, hc_sr04::Distance, pac, HcSr04, Led, Ultrasonic};
+use mikoto_bot::{
+    hal::prelude::*, hc_sr04::Distance, pac, EchoClock, HcSr04, Led, Temperature, Ultrasonic,
+};
 
 #[entry]
 fn main() -> ! {
@@ -20,14 +22,15 @@ fn main() -> ! {
     let clocks = rcc.cfgr.use_hse(8.MHz()).freeze();
 
     let mut delay = dp.TIM5.delay_us(&clocks);
-    let counter = dp.TIM2.counter_us(&clocks);
+    // Times the echo, wrapping every 100ms
+    let clock = EchoClock::new(dp.TIM2.counter_us(&clocks), 100_000).unwrap();
 
     let gpioa = dp.GPIOA.split();
 
     // Configure ultrasonic sensor
     let trig = gpioa.pa9;
     let echo = gpioa.pa8;
-    let mut ultrasonic = HcSr04::new(trig, echo, counter);
+    let mut ultrasonic = HcSr04::new(trig, echo);
 
     // Configure PA5 (LD2 - User LED) as an output
     let mut led = Led::new(gpioa.pa5);
@@ -36,9 +39,12 @@ fn main() -> ! {
     // Units are cm (type Distance<Cm>), which is elided from the later comparison with the read distance.
     let led_distance = Distance::new(25_u32);
 
+    // Ambient temperature, used to correct the speed of sound
+    let temperature = Temperature::from_celsius(22.0);
+
     defmt::info!("init");
     loop {
-        let distance = ultrasonic.read().unwrap().as_cm();
+        let distance = ultrasonic.read(&clock).unwrap().as_cm_at(temperature);
 
         // Print distance
         defmt::info!("Distance: {}", distance);
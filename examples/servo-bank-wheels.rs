@@ -0,0 +1,50 @@
+#![no_main]
+#![no_std]
+
+use cortex_m::peripheral::Peripherals;
+use cortex_m_rt::entry;
+use defmt_rtt as _;
+use panic_probe as _;
+
+use mikoto_bot::{hal::prelude::*, pac, Direction, Mikoto, ServoBank, Wheel};
+
+#[entry]
+fn main() -> ! {
+    // The Stm32 peripherals
+    let dp = pac::Peripherals::take().unwrap();
+    // The Cortex-m peripherals
+    let _core = Peripherals::take().unwrap();
+
+    // Constrain clock registers
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.use_hse(8.MHz()).freeze();
+
+    let mut delay = dp.TIM5.delay_us(&clocks);
+
+    let gpioa = dp.GPIOA.split();
+    let gpiob = dp.GPIOB.split();
+
+    // Front, left and right wheels on channels 1-3 of TIM3 (PA6, PA7, PB0)
+    let pins = (
+        gpioa.pa6.into_alternate(),
+        gpioa.pa7.into_alternate(),
+        gpiob.pb0.into_alternate(),
+    );
+    let bank = ServoBank::new(500, 2500, pins, dp.TIM3, &clocks).unwrap();
+    let (front_wheel, left_wheel, right_wheel) = bank.split();
+    let mut mikoto = Mikoto::builder(front_wheel, left_wheel, right_wheel)
+        .reversed(Wheel::Front, true)
+        .reversed(Wheel::Right, true)
+        .build()
+        .unwrap();
+
+    defmt::info!("init");
+    loop {
+        for direction in [Direction::Forward, Direction::Backward] {
+            mikoto.drive(direction, 50).unwrap();
+            delay.delay_ms(2000_u32);
+            mikoto.stop().unwrap();
+            delay.delay_ms(1000_u32);
+        }
+    }
+}
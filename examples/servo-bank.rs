@@ -0,0 +1,62 @@
+#![no_main]
+#![no_std]
+
+use cortex_m::peripheral::Peripherals;
+use cortex_m_rt::entry;
+use defmt_rtt as _;
+use panic_probe as _;
+
+use mikoto_bot::{
+    hal::{
+        prelude::*,
+        timer::Channel::{C1, C2, C3},
+    },
+    pac, InputRange, ServoBank, ServoRanges,
+};
+
+#[entry]
+fn main() -> ! {
+    // The Stm32 peripherals
+    let dp = pac::Peripherals::take().unwrap();
+    // The Cortex-m peripherals
+    let _core = Peripherals::take().unwrap();
+
+    // Constrain clock registers
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.use_hse(8.MHz()).freeze();
+
+    let mut delay = dp.TIM5.delay_us(&clocks);
+
+    let gpioa = dp.GPIOA.split();
+    let gpiob = dp.GPIOB.split();
+
+    // Three continuous servos on channels 1-3 of TIM3 (PA6, PA7, PB0)
+    let pins = (
+        gpioa.pa6.into_alternate(),
+        gpioa.pa7.into_alternate(),
+        gpiob.pb0.into_alternate(),
+    );
+    let mut servos = ServoBank::new(500, 2500, pins, dp.TIM3, &clocks).unwrap();
+    servos
+        .set_input_range(C1, InputRange::CONTINUOUS_RANGE.rev())
+        .unwrap();
+    servos
+        .set_input_range(C2, InputRange::CONTINUOUS_RANGE)
+        .unwrap();
+    servos
+        .set_input_range(C3, InputRange::CONTINUOUS_RANGE.rev())
+        .unwrap();
+    // The third servo has a narrower pulse range
+    servos.set_pulse(C3, 1000, 2000).unwrap();
+
+    defmt::info!("init");
+    loop {
+        for speed in [50, 0, -50, 0] {
+            defmt::info!("Set speed: {}", speed);
+            for channel in [C1, C2, C3] {
+                servos.set_position(channel, speed).unwrap();
+            }
+            delay.delay_ms(2000_u32);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+#![no_main]
+#![no_std]
+
+use cortex_m::peripheral::Peripherals;
+use cortex_m_rt::entry;
+use defmt_rtt as _;
+use panic_probe as _;
+
+use mikoto_bot::{hal::prelude::*, pac, MotionProfile, Servo};
+
+#[entry]
+fn main() -> ! {
+    // The Stm32 peripherals
+    let dp = pac::Peripherals::take().unwrap();
+    // The Cortex-m peripherals
+    let _core = Peripherals::take().unwrap();
+
+    // Constrain clock registers
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.use_hse(8.MHz()).freeze();
+
+    let mut delay = dp.TIM5.delay_us(&clocks);
+
+    let gpiob = dp.GPIOB.split();
+
+    // Configure a positional servo, e.g. for panning a distance sensor
+    let mut servo = Servo::new(500, 2500, gpiob.pb5.into_alternate(), dp.TIM3, &clocks).unwrap();
+
+    // Time (us) between updates
+    const TICK: u32 = 20_000;
+
+    defmt::info!("init");
+    loop {
+        // Sweep across the full range and back, taking 2s each way
+        for position in [180, 0] {
+            servo
+                .move_to(position, 2_000_000, MotionProfile::EaseInOut)
+                .unwrap();
+            while servo.is_moving() {
+                delay.delay_us(TICK);
+                servo.update(TICK);
+                defmt::debug!("Position: {}", servo.position());
+            }
+            defmt::info!("Reached {}", position);
+        }
+    }
+}
@@ -40,8 +40,7 @@ fn main() -> ! {
     let gpioc = dp.GPIOC.split();
 
     // Configure servo motor
-    let mut servo =
-        Servo::new(500.0, 2500.0, gpiob.pb5.into_alternate(), dp.TIM3, &clocks).unwrap();
+    let mut servo = Servo::new(500, 2500, gpiob.pb5.into_alternate(), dp.TIM3, &clocks).unwrap();
 
     // Configure PA5 (LD2 - User led) as an output
     let mut led = Led::new(gpioa.pa5);
@@ -55,7 +54,7 @@ fn main() -> ! {
     });
 
     // Optional: change the range of values we can use for inputs (default = 0-180)
-    servo.set_input_range(InputRange::CONTINUOUS_RANGE);
+    servo.set_input_range(InputRange::CONTINUOUS_RANGE).unwrap();
 
     // Enable the external interrupt
     unsafe {
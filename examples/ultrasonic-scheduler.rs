@@ -0,0 +1,99 @@
+#![no_main]
+#![no_std]
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::Peripherals;
+use cortex_m_rt::entry;
+use defmt_rtt as _;
+use panic_probe as _;
+
+use mikoto_bot::{
+    distance_unit::PulseDuration,
+    hal::{interrupt, prelude::*},
+    hc_sr04::Distance,
+    pac,
+    pac::TIM3,
+    EchoClock, EchoTiming, HcSr04, Ultrasonic, UltrasonicScheduler,
+};
+
+type Sensor = &'static mut (dyn EchoTiming<TIM3> + Send);
+type Scheduler = UltrasonicScheduler<Sensor, TIM3, 2>;
+
+static SCHEDULER: Mutex<RefCell<Option<Scheduler>>> = Mutex::new(RefCell::new(None));
+
+#[entry]
+fn main() -> ! {
+    // The Stm32 peripherals
+    let mut dp = pac::Peripherals::take().unwrap();
+    // The Cortex-m peripherals
+    let _core = Peripherals::take().unwrap();
+
+    // Constrain clock registers
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.use_hse(8.MHz()).freeze();
+
+    let mut syscfg = dp.SYSCFG.constrain();
+
+    let gpioa = dp.GPIOA.split();
+    let gpiob = dp.GPIOB.split();
+
+    // Configure the ultrasonic sensors
+    let front = HcSr04::new(gpioa.pa9, gpioa.pa8);
+    let side = HcSr04::new(gpiob.pb4, gpiob.pb5);
+    // The scheduler stores sensors of different types by reference, so they must live forever
+    let front = cortex_m::singleton!(: HcSr04<'A', 9, 'A', 8> = front).unwrap();
+    let side = cortex_m::singleton!(: HcSr04<'B', 4, 'B', 5> = side).unwrap();
+    front.enable_interrupt(&mut syscfg, &mut dp.EXTI);
+    side.enable_interrupt(&mut syscfg, &mut dp.EXTI);
+
+    // Wait 10ms after each measurement before firing the next sensor
+    let sensors: [Sensor; 2] = [front, side];
+    // Both sensors share one clock. The 16-bit TIM3 wraps at most every 65536 us, which is
+    // enough for an HC-SR04 at its default range, and leaves TIM2 and TIM5 free.
+    let clock = EchoClock::new(
+        dp.TIM3.counter_us(&clocks),
+        EchoClock::<TIM3>::MAX_16_BIT_PERIOD,
+    )
+    .unwrap();
+    let scheduler = UltrasonicScheduler::new(sensors, clock, 10_000).unwrap();
+
+    cortex_m::interrupt::free(|cs| {
+        SCHEDULER.borrow(cs).replace(Some(scheduler));
+    });
+
+    // Enable the external interrupt (PA8 and PB5)
+    unsafe {
+        cortex_m::peripheral::NVIC::unmask(pac::Interrupt::EXTI9_5);
+    }
+
+    defmt::info!("init");
+    loop {
+        let readings = cortex_m::interrupt::free(|cs| {
+            let mut scheduler = SCHEDULER.borrow(cs).borrow_mut();
+            let scheduler = scheduler.as_mut().unwrap();
+            scheduler.update();
+            *scheduler.readings()
+        });
+
+        for (i, reading) in readings.iter().enumerate() {
+            match reading {
+                Some(Ok(pulse)) => {
+                    let distance = Distance::<PulseDuration>::new(*pulse).as_cm();
+                    defmt::debug!("Sensor {}: {}", i, distance);
+                }
+                Some(Err(e)) => defmt::debug!("Sensor {}: {}", i, e),
+                None => {}
+            }
+        }
+    }
+}
+
+#[interrupt]
+fn EXTI9_5() {
+    cortex_m::interrupt::free(|cs| {
+        let mut scheduler = SCHEDULER.borrow(cs).borrow_mut();
+        scheduler.as_mut().unwrap().on_echo_edge();
+    });
+}
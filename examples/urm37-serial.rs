@@ -0,0 +1,44 @@
+#![no_main]
+#![no_std]
+
+use cortex_m::peripheral::Peripherals;
+use cortex_m_rt::entry;
+use defmt_rtt as _;
+use panic_probe as _;
+
+use mikoto_bot::{hal::prelude::*, pac, Urm37Serial, Usart};
+
+#[entry]
+fn main() -> ! {
+    // The Stm32 peripherals
+    let dp = pac::Peripherals::take().unwrap();
+    // The Cortex-m peripherals
+    let _core = Peripherals::take().unwrap();
+
+    // Constrain clock registers
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.use_hse(8.MHz()).freeze();
+
+    let mut delay = dp.TIM5.delay_us(&clocks);
+    let counter = dp.TIM2.counter_us(&clocks);
+
+    let gpioa = dp.GPIOA.split();
+
+    // Configure ultrasonic sensor on USART1 (Tx: PA9, Rx: PA10), which runs at 9600 baud
+    let usart = Usart::with_rx(gpioa.pa9, gpioa.pa10, dp.USART1, 9600.bps(), &clocks);
+    let mut ultrasonic = Urm37Serial::new(usart, counter);
+
+    defmt::info!("init");
+    loop {
+        match ultrasonic.read_temperature() {
+            Ok(temperature) => defmt::info!("Temperature: {}", temperature),
+            Err(e) => defmt::warn!("Temperature read failed! Error: {}", e),
+        }
+
+        match ultrasonic.read_distance() {
+            Ok(distance) => defmt::info!("Distance: {}", distance),
+            Err(e) => defmt::warn!("Distance read failed! Error: {}", e),
+        }
+        delay.delay_ms(300_u32);
+    }
+}
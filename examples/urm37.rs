@@ -6,7 +6,7 @@ use cortex_m_rt::entry;
 use defmt_rtt as _;
 use panic_probe as _;
 
-use mikoto_bot::{hal::prelude::*, pac, urm37::Distance, Led, Ultrasonic, Urm37};
+use mikoto_bot::{hal::prelude::*, pac, urm37::Distance, EchoClock, Led, Ultrasonic, Urm37};
 
 #[entry]
 fn main() -> ! {
@@ -20,14 +20,15 @@ fn main() -> ! {
     let clocks = rcc.cfgr.use_hse(8.MHz()).freeze();
 
     let mut delay = dp.TIM5.delay_us(&clocks);
-    let counter = dp.TIM2.counter_us(&clocks);
+    // Times the echo. Echoes at full range need the 32-bit TIM2, wrapping every 100ms
+    let clock = EchoClock::new(dp.TIM2.counter_us(&clocks), 100_000).unwrap();
 
     let gpioa = dp.GPIOA.split();
 
     // Configure ultrasonic sensor
     let trig = gpioa.pa9;
     let echo = gpioa.pa8;
-    let mut ultrasonic = Urm37::new(trig, echo, counter);
+    let mut ultrasonic = Urm37::new(trig, echo);
 
     // Configure PA5 (LD2 - User LED) as an output
     let mut led = Led::new(gpioa.pa5);
@@ -38,7 +39,7 @@ fn main() -> ! {
 
     defmt::info!("init");
     loop {
-        let distance = ultrasonic.read().unwrap().as_cm();
+        let distance = ultrasonic.read(&clock).unwrap().as_cm();
 
         // Print distance
         defmt::info!("Distance: {}", distance);
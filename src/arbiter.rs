@@ -0,0 +1,164 @@
+/// Priority of a command source, from lowest to highest
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, defmt::Format)]
+pub enum Priority {
+    /// Mission logic working through the course
+    Mission,
+    /// Turning on the spot
+    Turn,
+    /// Manual control
+    Teleop,
+    /// Safety monitor, e.g. stopping for a stall or obstacle
+    Safety,
+}
+
+impl Priority {
+    const COUNT: usize = 4;
+}
+
+/// Velocities a source wants to drive at, in wheel speed units, as for `Mikoto::drive_velocity`
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct MotionCommand {
+    /// Forward velocity
+    pub linear: f32,
+    /// Counter-clockwise (left) angular velocity
+    pub angular: f32,
+}
+
+impl MotionCommand {
+    pub const STOP: Self = Self::new(0.0, 0.0);
+
+    pub const fn new(linear: f32, angular: f32) -> Self {
+        Self { linear, angular }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Slot {
+    command: MotionCommand,
+    /// Time (us) left before the command expires
+    remaining: u32,
+}
+
+/// Chooses which source drives the wheels. Each priority holds its latest command until it
+/// expires or is cancelled, and the highest priority command still held wins.
+///
+/// Controllers produce commands without driving the wheels through `Mikoto::turn_command`,
+/// `straight_command`, `profile_command` and `wall_command`. `Mikoto::drive_arbitrated`
+/// then drives with the winner.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CommandArbiter {
+    slots: [Option<Slot>; Priority::COUNT],
+}
+
+impl CommandArbiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Publish `command` at `priority`, replacing its previous command. The command expires
+    /// after `lifetime` (us), so a source that stops publishing loses control.
+    pub fn publish(&mut self, priority: Priority, command: MotionCommand, lifetime: u32) {
+        self.slots[priority as usize] = Some(Slot {
+            command,
+            remaining: lifetime,
+        });
+    }
+
+    /// Withdraw the command at `priority`, handing control back to lower priorities
+    pub fn cancel(&mut self, priority: Priority) {
+        self.slots[priority as usize] = None;
+    }
+
+    /// Withdraw all commands
+    pub fn clear(&mut self) {
+        self.slots = [None; Priority::COUNT];
+    }
+
+    /// Age the commands by `dt` (us), dropping expired ones, and return the winning command
+    /// with its priority. `None` when every command has expired.
+    pub fn update(&mut self, dt: u32) -> Option<(Priority, MotionCommand)> {
+        for slot in &mut self.slots {
+            if let Some(held) = slot {
+                if held.remaining <= dt {
+                    *slot = None;
+                } else {
+                    held.remaining -= dt;
+                }
+            }
+        }
+        self.current()
+    }
+
+    /// Winning command with its priority, without aging the commands
+    pub fn current(&self) -> Option<(Priority, MotionCommand)> {
+        [
+            Priority::Safety,
+            Priority::Teleop,
+            Priority::Turn,
+            Priority::Mission,
+        ]
+        .into_iter()
+        .find_map(|priority| self.slots[priority as usize].map(|slot| (priority, slot.command)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORWARD: MotionCommand = MotionCommand::new(50.0, 0.0);
+    const TURN: MotionCommand = MotionCommand::new(0.0, 30.0);
+
+    #[test]
+    fn highest_priority_wins() {
+        let mut arbiter = CommandArbiter::new();
+        arbiter.publish(Priority::Mission, FORWARD, 1_000);
+        assert_eq!(arbiter.current(), Some((Priority::Mission, FORWARD)));
+
+        arbiter.publish(Priority::Turn, TURN, 1_000);
+        assert_eq!(arbiter.current(), Some((Priority::Turn, TURN)));
+
+        arbiter.publish(Priority::Safety, MotionCommand::STOP, 1_000);
+        // A lower priority publishing again doesn't take over
+        arbiter.publish(Priority::Teleop, FORWARD, 1_000);
+        assert_eq!(
+            arbiter.current(),
+            Some((Priority::Safety, MotionCommand::STOP))
+        );
+    }
+
+    #[test]
+    fn expired_commands_hand_back_control() {
+        let mut arbiter = CommandArbiter::new();
+        arbiter.publish(Priority::Mission, FORWARD, 1_000);
+        arbiter.publish(Priority::Turn, TURN, 300);
+
+        assert_eq!(arbiter.update(200), Some((Priority::Turn, TURN)));
+        // Expires once its whole lifetime has passed
+        assert_eq!(arbiter.update(100), Some((Priority::Mission, FORWARD)));
+        assert_eq!(arbiter.update(600), Some((Priority::Mission, FORWARD)));
+
+        // Publishing again renews the lifetime
+        arbiter.publish(Priority::Mission, TURN, 1_000);
+        assert_eq!(arbiter.update(900), Some((Priority::Mission, TURN)));
+        assert_eq!(arbiter.update(100), None);
+        assert_eq!(arbiter.current(), None);
+    }
+
+    #[test]
+    fn cancel_and_clear_withdraw_commands() {
+        let mut arbiter = CommandArbiter::new();
+        arbiter.publish(Priority::Mission, FORWARD, 1_000);
+        arbiter.publish(Priority::Safety, MotionCommand::STOP, 1_000);
+
+        arbiter.cancel(Priority::Safety);
+        assert_eq!(arbiter.update(0), Some((Priority::Mission, FORWARD)));
+        // Cancelling an empty priority does nothing
+        arbiter.cancel(Priority::Teleop);
+        assert_eq!(arbiter.current(), Some((Priority::Mission, FORWARD)));
+
+        arbiter.publish(Priority::Turn, TURN, 1_000);
+        arbiter.clear();
+        assert_eq!(arbiter.update(0), None);
+    }
+}
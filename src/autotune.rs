@@ -0,0 +1,276 @@
+use crate::mpu6050::wrap;
+use core::f32::consts;
+
+/// PID gains in the form used by `HeadingConfig`, with `ki` and `kd` per second
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct PidGains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+/// Ultimate gain and period of the heading loop, where a proportional controller would
+/// oscillate steadily. Gain tuning rules derive PID gains from these.
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct UltimateGain {
+    /// Ultimate gain, in wheel speed units per radian
+    pub gain: f32,
+    /// Ultimate period (s)
+    pub period: f32,
+}
+
+impl UltimateGain {
+    /// Describing function analysis of a relay oscillation. `relay` is the relay output
+    /// amplitude (wheel speed units), `hysteresis` and `amplitude` are the relay's switching
+    /// band and the heading oscillation's amplitude (rad), `period` is the oscillation period (s).
+    ///
+    /// Returns `None` unless the amplitude exceeds the hysteresis, as the gain is unbounded.
+    pub fn from_relay(relay: f32, hysteresis: f32, amplitude: f32, period: f32) -> Option<Self> {
+        if amplitude <= hysteresis {
+            return None;
+        }
+        // Ku = 4d / (π √(a² - ε²))
+        let amplitude = libm::sqrtf(amplitude * amplitude - hysteresis * hysteresis);
+        Some(Self {
+            gain: 4.0 * relay / (consts::PI * amplitude),
+            period,
+        })
+    }
+
+    /// Classic Ziegler–Nichols PID gains. Responsive, but with about 25% overshoot.
+    pub fn ziegler_nichols(&self) -> PidGains {
+        self.gains(0.6 * self.gain, 0.5 * self.period, 0.125 * self.period)
+    }
+
+    /// Tyreus–Luyben PID gains. Slower, with less overshoot and more robustness than
+    /// Ziegler–Nichols.
+    pub fn tyreus_luyben(&self) -> PidGains {
+        self.gains(self.gain / 2.2, 2.2 * self.period, self.period / 6.3)
+    }
+
+    /// Converts a gain and integral and derivative times (s) to gains
+    fn gains(&self, kp: f32, integral_time: f32, derivative_time: f32) -> PidGains {
+        PidGains {
+            kp,
+            ki: kp / integral_time,
+            kd: kp * derivative_time,
+        }
+    }
+}
+
+/// Progress of a heading autotune
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub enum AutotuneStatus {
+    /// Still oscillating
+    InProgress,
+    /// Measured the requested number of cycles
+    Done(UltimateGain),
+    /// The oscillation stayed within the hysteresis, so no gains can be derived
+    NoOscillation,
+    /// No steady oscillation before the timeout
+    TimedOut,
+}
+
+/// Relay autotune of the heading controller. The robot turns left at full relay speed while
+/// right of the heading and right while left of it, and the resulting oscillation is measured.
+pub struct RelayAutotune {
+    target: f32,
+    relay: u32,
+    hysteresis: f32,
+    cycles: usize,
+    timeout: u32,
+    turning_left: bool,
+    elapsed: u32,
+    /// Time (us) the robot last started turning left
+    cycle_start: Option<u32>,
+    /// Smallest and largest heading error (rad) this cycle
+    min_error: f32,
+    max_error: f32,
+    measured: usize,
+    period_sum: f32,
+    amplitude_sum: f32,
+}
+
+impl RelayAutotune {
+    /// Cycles ignored while the oscillation settles
+    const SKIP_CYCLES: usize = 1;
+
+    /// Oscillate around `target` (rad) turning at `relay` speed, switching direction once the
+    /// heading error is beyond `hysteresis` (rad). Measures `cycles` oscillations, giving up
+    /// after `timeout` (us). Returns `None` if `cycles` is zero.
+    pub fn new(
+        target: f32,
+        relay: u32,
+        hysteresis: f32,
+        cycles: usize,
+        timeout: u32,
+    ) -> Option<Self> {
+        if cycles == 0 {
+            return None;
+        }
+        Some(Self {
+            target,
+            relay,
+            hysteresis,
+            cycles,
+            timeout,
+            turning_left: true,
+            elapsed: 0,
+            cycle_start: None,
+            min_error: 0.0,
+            max_error: 0.0,
+            measured: 0,
+            period_sum: 0.0,
+            amplitude_sum: 0.0,
+        })
+    }
+
+    /// Feed the current yaw (rad, increasing to the left) and the time (us) since the last
+    /// update. Check `turning_left` for the relay output afterwards.
+    pub fn update(&mut self, yaw: f32, dt: u32) -> AutotuneStatus {
+        self.elapsed = self.elapsed.saturating_add(dt);
+        if self.elapsed >= self.timeout {
+            return AutotuneStatus::TimedOut;
+        }
+
+        let error = wrap(self.target - yaw);
+        self.min_error = self.min_error.min(error);
+        self.max_error = self.max_error.max(error);
+
+        if !self.turning_left && error > self.hysteresis {
+            // A full cycle ends each time the relay switches to turning left
+            self.turning_left = true;
+            if let Some(start) = self.cycle_start {
+                self.measure_cycle(self.elapsed - start);
+            }
+            self.cycle_start = Some(self.elapsed);
+            self.min_error = error;
+            self.max_error = error;
+        } else if self.turning_left && error < -self.hysteresis {
+            self.turning_left = false;
+        }
+
+        if self.measured >= Self::SKIP_CYCLES + self.cycles {
+            let n = self.cycles as f32;
+            UltimateGain::from_relay(
+                self.relay as f32,
+                self.hysteresis,
+                self.amplitude_sum / n,
+                self.period_sum / n,
+            )
+            .map_or(AutotuneStatus::NoOscillation, AutotuneStatus::Done)
+        } else {
+            AutotuneStatus::InProgress
+        }
+    }
+
+    /// Whether the relay is turning the robot left
+    pub fn turning_left(&self) -> bool {
+        self.turning_left
+    }
+
+    /// Relay output amplitude, in wheel speed units
+    pub fn relay(&self) -> u32 {
+        self.relay
+    }
+
+    fn measure_cycle(&mut self, period: u32) {
+        self.measured += 1;
+        if self.measured > Self::SKIP_CYCLES {
+            self.period_sum += period as f32 * 1e-6;
+            self.amplitude_sum += (self.max_error - self.min_error) / 2.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: u32 = 10_000;
+    /// Turning rate (rad/s) at the relay speed
+    const RATE: f32 = 1.0;
+
+    /// Runs the relay on a robot that turns at `RATE`, responding `delay` updates late
+    fn oscillate(autotune: &mut RelayAutotune, delay: usize) -> AutotuneStatus {
+        let mut yaw = 0.0;
+        let mut history = [true; 64];
+        for step in 0..100_000 {
+            let status = autotune.update(yaw, DT);
+            if status != AutotuneStatus::InProgress {
+                return status;
+            }
+            history[step % delay] = autotune.turning_left();
+            let turning_left = history[(step + 1) % delay];
+            let rate = if turning_left { RATE } else { -RATE };
+            yaw += rate * DT as f32 * 1e-6;
+        }
+        AutotuneStatus::InProgress
+    }
+
+    fn assert_near(value: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (value - expected).abs() <= tolerance,
+            "{value} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn rejects_zero_cycles() {
+        assert!(RelayAutotune::new(0.0, 20, 0.05, 0, 10_000_000).is_none());
+    }
+
+    #[test]
+    fn measures_delayed_oscillation() {
+        let hysteresis = 0.05;
+        let mut autotune = RelayAutotune::new(0.0, 20, hysteresis, 3, 60_000_000).unwrap();
+        let AutotuneStatus::Done(ultimate) = oscillate(&mut autotune, 10) else {
+            panic!("autotune did not finish");
+        };
+
+        // The robot overshoots the hysteresis by the turn made during the 0.1 s delay
+        let amplitude: f32 = hysteresis + 0.1 * RATE;
+        let period = 4.0 * amplitude / RATE;
+        let gain = 4.0 * 20.0
+            / (consts::PI * libm::sqrtf(amplitude * amplitude - hysteresis * hysteresis));
+        assert_near(ultimate.period, period, 0.03);
+        assert_near(ultimate.gain, gain, 0.05 * gain);
+    }
+
+    #[test]
+    fn times_out_without_oscillation() {
+        let mut autotune = RelayAutotune::new(0.0, 20, 0.05, 3, 1_000_000).unwrap();
+        let mut status = AutotuneStatus::InProgress;
+        for _ in 0..100 {
+            // Stuck, so the heading never leaves the hysteresis band
+            status = autotune.update(0.0, DT);
+        }
+        assert_eq!(status, AutotuneStatus::TimedOut);
+    }
+
+    #[test]
+    fn relay_gain_from_describing_function() {
+        let ultimate = UltimateGain::from_relay(10.0, 0.0, 1.0, 2.0).unwrap();
+        assert_near(ultimate.gain, 40.0 / consts::PI, 1e-4);
+        assert!(UltimateGain::from_relay(10.0, 0.1, 0.1, 2.0).is_none());
+    }
+
+    #[test]
+    fn tuning_rules() {
+        let ultimate = UltimateGain {
+            gain: 2.0,
+            period: 1.0,
+        };
+
+        let zn = ultimate.ziegler_nichols();
+        assert_near(zn.kp, 1.2, 1e-5);
+        assert_near(zn.ki, 1.2 / 0.5, 1e-5);
+        assert_near(zn.kd, 1.2 * 0.125, 1e-6);
+
+        let tl = ultimate.tyreus_luyben();
+        let kp = 2.0 / 2.2;
+        assert_near(tl.kp, kp, 1e-5);
+        assert_near(tl.ki, kp / 2.2, 1e-6);
+        assert_near(tl.kd, kp / 6.3, 1e-6);
+    }
+}
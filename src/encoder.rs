@@ -0,0 +1,259 @@
+use crate::hal::{
+    gpio::{Edge, ExtiPin, Input, Pin, PinMode},
+    pac::EXTI,
+    qei::{self, Qei},
+    syscfg::SysCfg,
+};
+
+/// Source of wheel encoder counts
+pub trait PulseCounter {
+    /// Current count. Only the low 16 bits are used, so it may wrap at any multiple of 2^16.
+    fn count(&self) -> u16;
+}
+
+impl<TIM: qei::Instance, PINS> PulseCounter for Qei<TIM, PINS>
+where
+    Qei<TIM, PINS>: embedded_hal::Qei,
+    <Qei<TIM, PINS> as embedded_hal::Qei>::Count: Into<u32>,
+{
+    fn count(&self) -> u16 {
+        embedded_hal::Qei::count(self).into() as u16
+    }
+}
+
+/// Counts edges of a single-channel encoder with an external interrupt, for sensors or pins
+/// without a timer encoder mode. The direction can't be sensed, so it must be set to match
+/// the wheel's commanded direction.
+pub struct ExtiCounter<const P: char, const N: u8> {
+    pin: Pin<P, N, Input>,
+    count: u16,
+    forward: bool,
+}
+
+impl<const P: char, const N: u8> ExtiCounter<P, N> {
+    pub fn new(pin: Pin<P, N, impl PinMode>) -> Self {
+        Self {
+            pin: pin.into_pull_down_input(),
+            count: 0,
+            forward: true,
+        }
+    }
+
+    pub fn enable_interrupt(&mut self, edge: Edge, syscfg: &mut SysCfg, exti: &mut EXTI) {
+        self.pin.make_interrupt_source(syscfg);
+        self.pin.trigger_on_edge(exti, edge);
+        self.pin.enable_interrupt(exti);
+    }
+
+    /// Count an edge. Call from the pin's EXTI interrupt handler.
+    pub fn on_edge(&mut self) {
+        self.pin.clear_interrupt_pending_bit();
+        self.count = if self.forward {
+            self.count.wrapping_add(1)
+        } else {
+            self.count.wrapping_sub(1)
+        };
+    }
+
+    /// Set whether edges count up (forward) or down
+    pub fn set_forward(&mut self, forward: bool) {
+        self.forward = forward;
+    }
+}
+
+impl<const P: char, const N: u8> PulseCounter for ExtiCounter<P, N> {
+    fn count(&self) -> u16 {
+        self.count
+    }
+}
+
+/// Wheel encoder reporting counts, distance and velocity
+///
+/// Counts, distance and velocity are positive in the direction the counter counts up. A wheel
+/// mounted reversed (see `MikotoBuilder::reversed`) spins its encoder the other way for the
+/// same robot motion, so swap its encoder channels or negate its readings to keep them
+/// positive when driving forward.
+pub struct Encoder<C: PulseCounter> {
+    counter: C,
+    last: u16,
+    counts: i32,
+    /// Distance (mm) travelled per count
+    mm_per_count: f32,
+    /// Velocity (mm/s) over the last update
+    velocity: f32,
+}
+
+impl<C: PulseCounter> Encoder<C> {
+    /// `counts_per_rev` is counted by the counter per wheel revolution, and
+    /// `circumference` (mm) is the distance the wheel rolls in one revolution.
+    pub fn new(counter: C, counts_per_rev: u32, circumference: f32) -> Self {
+        Self {
+            last: counter.count(),
+            counter,
+            counts: 0,
+            mm_per_count: circumference / counts_per_rev as f32,
+            velocity: 0.0,
+        }
+    }
+
+    /// Read the counter and return the velocity (mm/s) since the last update, `dt` (us) ago.
+    /// Update often enough that the counter moves less than 2^15 counts between updates.
+    pub fn update(&mut self, dt: u32) -> f32 {
+        let count = self.counter.count();
+        let delta = count.wrapping_sub(self.last) as i16 as i32;
+        self.last = count;
+        self.counts += delta;
+        if dt > 0 {
+            self.velocity = delta as f32 * self.mm_per_count / (dt as f32 * 1e-6);
+        }
+        self.velocity
+    }
+
+    /// Counts since the start or the last reset
+    pub fn counts(&self) -> i32 {
+        self.counts
+    }
+
+    /// Distance (mm) since the start or the last reset
+    pub fn distance(&self) -> f32 {
+        self.counts as f32 * self.mm_per_count
+    }
+
+    /// Velocity (mm/s) at the last update
+    pub fn velocity(&self) -> f32 {
+        self.velocity
+    }
+
+    /// Zero the counts and distance
+    pub fn reset(&mut self) {
+        self.counts = 0;
+    }
+
+    pub fn counter(&mut self) -> &mut C {
+        &mut self.counter
+    }
+
+    pub fn release(self) -> C {
+        self.counter
+    }
+}
+
+/// Gains of the closed-loop wheel speed control, correcting each wheel's command from its
+/// measured speed
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct SpeedControlGains {
+    /// Wheel speed units per mm/s of speed error
+    pub kp: f32,
+    /// Wheel speed units per mm of accumulated speed error
+    pub ki: f32,
+}
+
+/// PI correction of one wheel's speed command
+#[derive(Debug, Copy, Clone, Default)]
+pub(crate) struct WheelSpeedController {
+    integral: f32,
+}
+
+impl WheelSpeedController {
+    /// Largest correction the integral can build up, in wheel speed units
+    const INTEGRAL_LIMIT: f32 = 50.0;
+
+    /// Returns the corrected command for `command` given the `target` and `measured` speeds
+    /// (mm/s), `dt` (us) since the last update.
+    pub fn update(
+        &mut self,
+        gains: SpeedControlGains,
+        command: i32,
+        target: f32,
+        measured: f32,
+        dt: u32,
+    ) -> f32 {
+        if command == 0 {
+            // Let a stopped wheel stop, rather than chase encoder noise
+            self.integral = 0.0;
+            return 0.0;
+        }
+        let error = target - measured;
+        self.integral = (self.integral + gains.ki * error * dt as f32 * 1e-6)
+            .clamp(-Self::INTEGRAL_LIMIT, Self::INTEGRAL_LIMIT);
+        command as f32 + gains.kp * error + self.integral
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockCounter(u16);
+
+    impl PulseCounter for MockCounter {
+        fn count(&self) -> u16 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn counts_across_wraparound() {
+        let mut encoder = Encoder::new(MockCounter(65_530), 100, 200.0);
+        encoder.counter().0 = 4;
+        // 10 counts of 2 mm in 0.1 s
+        assert_eq!(encoder.update(100_000), 200.0);
+        assert_eq!(encoder.counts(), 10);
+        assert_eq!(encoder.distance(), 20.0);
+
+        encoder.counter().0 = 65_530;
+        assert_eq!(encoder.update(100_000), -200.0);
+        assert_eq!(encoder.counts(), 0);
+    }
+
+    #[test]
+    fn counting_down_is_backward() {
+        let mut encoder = Encoder::new(MockCounter(1_000), 100, 200.0);
+        encoder.counter().0 = 950;
+        assert_eq!(encoder.update(500_000), -200.0);
+        assert_eq!(encoder.distance(), -100.0);
+
+        encoder.reset();
+        assert_eq!(encoder.distance(), 0.0);
+        // A zero `dt` keeps the last velocity
+        assert_eq!(encoder.update(0), -200.0);
+    }
+
+    #[test]
+    fn speed_controller_converges_on_target() {
+        let gains = SpeedControlGains { kp: 0.1, ki: 2.0 };
+        let mut controller = WheelSpeedController::default();
+        // The wheel is slower than the speed model expects: 1.5 mm/s per unit rather than 2
+        let command = 40;
+        let target = 80.0;
+        let mut measured = 0.0;
+        for _ in 0..500 {
+            let output = controller.update(gains, command, target, measured, 20_000);
+            measured = 1.5 * output;
+        }
+        assert!((measured - target).abs() < 0.5);
+
+        // Reversing converges the same way
+        let mut controller = WheelSpeedController::default();
+        let mut measured = 0.0;
+        for _ in 0..500 {
+            let output = controller.update(gains, -command, -target, measured, 20_000);
+            measured = 1.5 * output;
+        }
+        assert!((measured + target).abs() < 0.5);
+    }
+
+    #[test]
+    fn speed_controller_lets_stopped_wheel_stop() {
+        let gains = SpeedControlGains { kp: 0.1, ki: 2.0 };
+        let mut controller = WheelSpeedController::default();
+        controller.update(gains, 40, 80.0, 0.0, 1_000_000);
+        assert_eq!(controller.update(gains, 0, 0.0, 30.0, 20_000), 0.0);
+        // The integral was dropped, so a new command starts fresh
+        assert_eq!(controller.update(gains, 40, 80.0, 80.0, 0), 40.0);
+    }
+}
@@ -0,0 +1,269 @@
+/// Filter for a stream of range sensor readings
+pub trait RangeFilter {
+    /// Add a reading and return the filtered value
+    fn update(&mut self, sample: f32) -> f32;
+
+    /// Forget all previous readings
+    fn reset(&mut self);
+
+    /// Feed the output of this filter into `next`
+    fn then<F: RangeFilter>(self, next: F) -> Chain<Self, F>
+    where
+        Self: Sized,
+    {
+        Chain(self, next)
+    }
+}
+
+/// Two filters applied one after the other
+pub struct Chain<A, B>(A, B);
+
+impl<A: RangeFilter, B: RangeFilter> RangeFilter for Chain<A, B> {
+    fn update(&mut self, sample: f32) -> f32 {
+        let sample = self.0.update(sample);
+        self.1.update(sample)
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+        self.1.reset();
+    }
+}
+
+/// The last N readings
+#[derive(Debug, Clone, Copy)]
+struct Window<const N: usize> {
+    samples: [f32; N],
+    len: usize,
+    next: usize,
+}
+
+impl<const N: usize> Window<N> {
+    const fn new() -> Self {
+        // An empty window has no readings to filter
+        const { assert!(N > 0, "filter window must hold at least one reading") };
+        Self {
+            samples: [0.0; N],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.samples[self.next] = sample;
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+
+    fn median(&self) -> f32 {
+        let mut sorted = self.samples;
+        median(&mut sorted[..self.len])
+    }
+
+    /// Median absolute deviation from `center`
+    fn mad(&self, center: f32) -> f32 {
+        let mut deviations = self.samples;
+        for d in deviations[..self.len].iter_mut() {
+            *d = libm::fabsf(*d - center);
+        }
+        median(&mut deviations[..self.len])
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_unstable_by(f32::total_cmp);
+    let mid = values.len() / 2;
+    if values.len() % 2 == 1 {
+        values[mid]
+    } else {
+        (values[mid - 1] + values[mid]) / 2.0
+    }
+}
+
+/// Median of the last N readings. Rejects single spikes at the cost of N/2 readings of lag.
+#[derive(Debug, Clone, Copy)]
+pub struct Median<const N: usize> {
+    window: Window<N>,
+}
+
+impl<const N: usize> Median<N> {
+    pub const fn new() -> Self {
+        Self {
+            window: Window::new(),
+        }
+    }
+}
+
+impl<const N: usize> Default for Median<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> RangeFilter for Median<N> {
+    fn update(&mut self, sample: f32) -> f32 {
+        self.window.push(sample);
+        self.window.median()
+    }
+
+    fn reset(&mut self) {
+        self.window.clear();
+    }
+}
+
+/// Hampel filter. Readings further than `threshold` standard deviations from the median of
+/// the last N readings are replaced by that median, all others pass through unchanged.
+#[derive(Debug, Clone, Copy)]
+pub struct Hampel<const N: usize> {
+    window: Window<N>,
+    threshold: f32,
+}
+
+impl<const N: usize> Hampel<N> {
+    /// Scales the median absolute deviation to a standard deviation for normally distributed noise
+    const MAD_SCALE: f32 = 1.4826;
+
+    /// `threshold` is commonly 3 standard deviations
+    pub const fn new(threshold: f32) -> Self {
+        Self {
+            window: Window::new(),
+            threshold,
+        }
+    }
+}
+
+impl<const N: usize> RangeFilter for Hampel<N> {
+    fn update(&mut self, sample: f32) -> f32 {
+        self.window.push(sample);
+        let median = self.window.median();
+        let sigma = Self::MAD_SCALE * self.window.mad(median);
+        if libm::fabsf(sample - median) > self.threshold * sigma {
+            median
+        } else {
+            sample
+        }
+    }
+
+    fn reset(&mut self) {
+        self.window.clear();
+    }
+}
+
+/// Exponential moving average
+#[derive(Debug, Clone, Copy)]
+pub struct Exponential {
+    alpha: f32,
+    value: Option<f32>,
+}
+
+impl Exponential {
+    /// `alpha` (0-1] is the weight given to each new reading. Lower values smooth more.
+    pub const fn new(alpha: f32) -> Self {
+        Self { alpha, value: None }
+    }
+}
+
+impl RangeFilter for Exponential {
+    fn update(&mut self, sample: f32) -> f32 {
+        let value = match self.value {
+            None => sample,
+            Some(value) => value + self.alpha * (sample - value),
+        };
+        self.value = Some(value);
+        value
+    }
+
+    fn reset(&mut self) {
+        self.value = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median_rejects_single_spike() {
+        let mut filter = Median::<3>::new();
+        let outputs: [f32; 5] = [100.0, 102.0, 5000.0, 101.0, 103.0].map(|s| filter.update(s));
+        assert!(outputs.iter().all(|&output| output < 110.0));
+    }
+
+    #[test]
+    fn median_warms_up_over_partial_window() {
+        let mut filter = Median::<5>::new();
+        assert_eq!(filter.update(100.0), 100.0);
+        assert_eq!(filter.update(200.0), 150.0);
+        assert_eq!(filter.update(120.0), 120.0);
+    }
+
+    #[test]
+    fn median_reset_forgets_readings() {
+        let mut filter = Median::<3>::new();
+        filter.update(1000.0);
+        filter.update(1000.0);
+        filter.reset();
+        assert_eq!(filter.update(50.0), 50.0);
+    }
+
+    #[test]
+    fn hampel_replaces_outlier_with_median() {
+        let mut filter = Hampel::<5>::new(3.0);
+        for sample in [100.0, 101.0, 99.0, 100.0] {
+            assert_eq!(filter.update(sample), sample);
+        }
+        assert_eq!(filter.update(800.0), 100.0);
+    }
+
+    #[test]
+    fn hampel_passes_first_readings() {
+        let mut filter = Hampel::<5>::new(3.0);
+        assert_eq!(filter.update(300.0), 300.0);
+        assert_eq!(filter.update(310.0), 310.0);
+    }
+
+    #[test]
+    fn hampel_follows_step_change() {
+        let mut filter = Hampel::<3>::new(3.0);
+        for _ in 0..3 {
+            filter.update(100.0);
+        }
+        // Once most of the window has moved, the new level is no longer an outlier
+        filter.update(500.0);
+        assert_eq!(filter.update(500.0), 500.0);
+    }
+
+    #[test]
+    fn exponential_starts_at_first_reading() {
+        let mut filter = Exponential::new(0.25);
+        assert_eq!(filter.update(200.0), 200.0);
+        assert_eq!(filter.update(100.0), 175.0);
+    }
+
+    #[test]
+    fn exponential_converges_and_resets() {
+        let mut filter = Exponential::new(0.5);
+        filter.update(0.0);
+        let mut output = 0.0;
+        for _ in 0..30 {
+            output = filter.update(100.0);
+        }
+        assert!((output - 100.0).abs() < 1e-3);
+        filter.reset();
+        assert_eq!(filter.update(7.0), 7.0);
+    }
+
+    #[test]
+    fn chain_applies_filters_in_order() {
+        let mut filter = Median::<3>::new().then(Exponential::new(0.5));
+        filter.update(100.0);
+        filter.update(100.0);
+        // The spike is removed by the median before reaching the average
+        assert_eq!(filter.update(9000.0), 100.0);
+    }
+}
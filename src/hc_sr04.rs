@@ -1,58 +1,185 @@
 use crate::hal::{
-    gpio::{Input, Output, Pin, PinMode, PushPull},
-    prelude::*,
-    timer::{CounterUs, Instance},
+    gpio::{Edge, ExtiPin, Input, Output, Pin, PinMode, PushPull},
+    nb,
+    pac::EXTI,
+    syscfg::SysCfg,
+    timer::Instance,
+};
+use crate::ultrasonic::{
+    Clocked, EchoCapture, EchoClock, EchoTiming, RangeError, RangeSensor, Temperature,
 };
 use crate::{distance_unit::*, Ultrasonic};
 use core::{cmp::Ordering, fmt, marker::PhantomData};
 
-/// HC-SR04 Ultrasonic sensor
-pub struct HcSr04<TIM: Instance, const P1: char, const N1: u8, const P2: char, const N2: u8> {
+/// HC-SR04 Ultrasonic sensor. Timed by a borrowed `EchoClock`, which any number of sensors can
+/// share.
+pub struct HcSr04<const P1: char, const N1: u8, const P2: char, const N2: u8> {
     trigger: Pin<P1, N1, Output<PushPull>>,
     echo: Pin<P2, N2, Input>,
-    counter: CounterUs<TIM>,
+    capture: EchoCapture,
+    max_pulse: u32,
+    min_interval: u32,
+    /// Clock timestamp (us) of the last trigger
+    cycle_start: Option<u32>,
+    /// Air temperature for the speed of sound in `RangeSensor` readings
+    temperature: Temperature,
 }
-impl<TIM: Instance, const P1: char, const N1: u8, const P2: char, const N2: u8>
-    Ultrasonic<TIM, P1, N1, P2, N2> for HcSr04<TIM, P1, N1, P2, N2>
+impl<const P1: char, const N1: u8, const P2: char, const N2: u8> Ultrasonic<P1, N1, P2, N2>
+    for HcSr04<P1, N1, P2, N2>
 {
-    fn new(
-        trigger: Pin<P1, N1, impl PinMode>,
-        echo: Pin<P2, N2, impl PinMode>,
-        counter: CounterUs<TIM>,
-    ) -> Self {
+    fn new(trigger: Pin<P1, N1, impl PinMode>, echo: Pin<P2, N2, impl PinMode>) -> Self {
         let trigger = trigger.into_push_pull_output();
         let echo = echo.into_pull_down_input();
         Self {
             trigger,
             echo,
-            counter,
+            capture: EchoCapture::Idle,
+            max_pulse: Distance::<Cm>::new(Self::MAX_RANGE).as_pulse().value(),
+            min_interval: Self::MIN_INTERVAL,
+            cycle_start: None,
+            temperature: Temperature::ROOM,
         }
     }
 }
 
-impl<TIM: Instance, const P1: char, const N1: u8, const P2: char, const N2: u8>
-    HcSr04<TIM, P1, N1, P2, N2>
-{
-    pub fn read(&mut self) -> Option<Distance<PulseDuration>> {
-        // 1 second timeout
-        self.counter.start(1_000_000_u32.micros()).unwrap();
+impl<const P1: char, const N1: u8, const P2: char, const N2: u8> HcSr04<P1, N1, P2, N2> {
+    /// Maximum range of the sensor (cm)
+    const MAX_RANGE: u32 = 400;
+    /// Minimum time (us) between triggers. Datasheet recommends a measurement cycle over 60ms.
+    const MIN_INTERVAL: u32 = 60_000;
+
+    pub fn read<TIM: Instance>(
+        &mut self,
+        clock: &EchoClock<TIM>,
+    ) -> Result<Distance<PulseDuration>, RangeError> {
+        self.check_cycle(clock)?;
+        // Wait for the previous measurement cycle to end
+        while !self.interval_elapsed(clock) {}
+        self.cycle_start = Some(clock.now());
+
+        self.send_trigger(clock);
+
+        // starting echo read
+        clock
+            .wait_until(|c| c.is_high(), &self.echo, self.max_pulse)
+            .ok_or(RangeError::Timeout)?;
+        let pulse_duration = clock
+            .wait_until(|c| c.is_low(), &self.echo, self.max_pulse)
+            .ok_or(RangeError::OutOfRange)?;
+
+        let distance: Distance<PulseDuration> = Distance(pulse_duration, PhantomData);
+        Ok(distance)
+    }
+
+    /// Enable the external interrupt on both edges of the echo pin, for `EchoTiming`
+    pub fn enable_interrupt(&mut self, syscfg: &mut SysCfg, exti: &mut EXTI) {
+        self.echo.make_interrupt_source(syscfg);
+        self.echo.trigger_on_edge(exti, Edge::RisingFalling);
+        self.echo.enable_interrupt(exti);
+    }
+
+    /// Reject echoes from targets further than `range`.
+    /// A shorter range also shortens the wait for a missing echo.
+    pub fn set_max_range(&mut self, range: Distance<Cm>) {
+        self.max_pulse = range.as_pulse().value();
+    }
+
+    pub fn max_range(&self) -> Distance<Cm> {
+        Distance::<PulseDuration>(self.max_pulse, PhantomData).as_cm()
+    }
+
+    /// Set the minimum time (us) between triggers, so a late echo from the previous
+    /// ping is not mistaken for the current one.
+    pub fn set_min_interval(&mut self, us: u32) {
+        self.min_interval = us;
+    }
+
+    /// Set the air temperature used to convert `RangeSensor` readings. Default = 20°C.
+    pub fn set_temperature(&mut self, temperature: Temperature) {
+        self.temperature = temperature;
+    }
+
+    pub fn temperature(&self) -> Temperature {
+        self.temperature
+    }
+
+    /// Range (mm) for an echo `pulse` (us), as `as_cm_at` but keeping the fraction of a cm
+    fn pulse_as_mm(&self, pulse: u32) -> f32 {
+        pulse as f32 * 10.0 / self.temperature.pulse_per_cm()
+    }
+
+    /// Fails if `clock` would wrap before the echo times out or the next trigger is due
+    fn check_cycle<TIM: Instance>(&self, clock: &EchoClock<TIM>) -> Result<(), RangeError> {
+        // The echo can start as late as `max_pulse` and then last as long again
+        clock
+            .check_interval(self.min_interval.max(2 * self.max_pulse))
+            .map_err(|_| RangeError::ClockTooShort)
+    }
+
+    /// Whether the minimum interval since the last trigger has passed
+    fn interval_elapsed<TIM: Instance>(&self, clock: &EchoClock<TIM>) -> bool {
+        self.cycle_start
+            .is_none_or(|start| clock.elapsed(start) >= self.min_interval)
+    }
 
+    /// Time (us) since the last trigger
+    fn since_trigger<TIM: Instance>(&self, clock: &EchoClock<TIM>) -> u32 {
+        self.cycle_start.map_or(0, |start| clock.elapsed(start))
+    }
+
+    fn send_trigger<TIM: Instance>(&mut self, clock: &EchoClock<TIM>) {
         // starting pulse
         self.trigger.set_low();
-        Self::waste(&self.counter, 2);
+        clock.delay(2);
         self.trigger.set_high();
-        Self::waste(&self.counter, 10);
+        clock.delay(10);
         self.trigger.set_low();
         // ending pulse
+    }
+}
 
-        // starting echo read
-        Self::waste_until(&self.counter, |c| c.is_high(), &self.echo, 1_000_000)?;
-        let pulse_duration =
-            Self::waste_until(&self.counter, |c| c.is_low(), &self.echo, 1_000_000)?;
+impl<TIM: Instance, const P1: char, const N1: u8, const P2: char, const N2: u8> EchoTiming<TIM>
+    for HcSr04<P1, N1, P2, N2>
+{
+    fn trigger(&mut self, clock: &EchoClock<TIM>) -> nb::Result<(), RangeError> {
+        self.check_cycle(clock)?;
+        if !self.interval_elapsed(clock) {
+            return Err(nb::Error::WouldBlock);
+        }
+        // Timestamps are taken relative to the trigger
+        self.cycle_start = Some(clock.now());
+        self.capture = EchoCapture::Waiting;
+        self.send_trigger(clock);
+        Ok(())
+    }
 
-        self.counter.cancel().unwrap();
-        let distance: Distance<PulseDuration> = Distance(pulse_duration, PhantomData);
-        Some(distance)
+    fn on_echo_edge(&mut self, clock: &EchoClock<TIM>) {
+        let now = self.since_trigger(clock);
+        self.echo.clear_interrupt_pending_bit();
+        self.capture.edge(self.echo.is_high(), now);
+    }
+
+    fn poll(&mut self, clock: &EchoClock<TIM>) -> nb::Result<u32, RangeError> {
+        let now = self.since_trigger(clock);
+        self.capture.take(now, self.max_pulse)
+    }
+}
+
+impl<TIM: Instance, const P1: char, const N1: u8, const P2: char, const N2: u8> RangeSensor
+    for Clocked<'_, HcSr04<P1, N1, P2, N2>, TIM>
+{
+    fn read_mm(&mut self) -> Result<f32, RangeError> {
+        let pulse = self.sensor.read(self.clock)?;
+        Ok(self.sensor.pulse_as_mm(pulse.value()))
+    }
+
+    fn start(&mut self) -> nb::Result<(), RangeError> {
+        self.sensor.trigger(self.clock)
+    }
+
+    fn poll_mm(&mut self) -> nb::Result<f32, RangeError> {
+        let pulse = self.sensor.poll(self.clock)?;
+        Ok(self.sensor.pulse_as_mm(pulse))
     }
 }
 
@@ -73,6 +200,7 @@ impl Distance<PulseDuration> {
     // 340 m/s * 100 cm/m * 10^-6 s/us = 0.034 cm/us.
     // Since the sound wave must reach the target then return,
     // distance (cm) = (pulse_duration * 0.034) / 2 = pulse_duration / 58
+    // Use `as_cm_at` to correct for the ambient temperature.
     pub fn as_cm(&self) -> Distance<Cm> {
         Distance(self.0 / 58, PhantomData)
     }
@@ -82,18 +210,42 @@ impl Distance<PulseDuration> {
     pub fn as_inch(&self) -> Distance<Inch> {
         Distance(self.0 / 148, PhantomData)
     }
+
+    /// Convert to cm using the speed of sound at the given air temperature
+    pub fn as_cm_at(&self, temperature: Temperature) -> Distance<Cm> {
+        let cm = self.0 as f32 / temperature.pulse_per_cm();
+        Distance(libm::roundf(cm) as u32, PhantomData)
+    }
+
+    /// Convert to inches using the speed of sound at the given air temperature
+    pub fn as_inch_at(&self, temperature: Temperature) -> Distance<Inch> {
+        let inch = self.0 as f32 / (temperature.pulse_per_cm() * 2.54);
+        Distance(libm::roundf(inch) as u32, PhantomData)
+    }
 }
 
 impl Distance<Cm> {
     pub fn as_pulse(&self) -> Distance<PulseDuration> {
         Distance(self.0 * 58, PhantomData)
     }
+
+    /// Expected echo pulse for this distance at the given air temperature
+    pub fn as_pulse_at(&self, temperature: Temperature) -> Distance<PulseDuration> {
+        let pulse = self.0 as f32 * temperature.pulse_per_cm();
+        Distance(libm::roundf(pulse) as u32, PhantomData)
+    }
 }
 
 impl Distance<Inch> {
     pub fn as_pulse(&self) -> Distance<PulseDuration> {
         Distance(self.0 * 148, PhantomData)
     }
+
+    /// Expected echo pulse for this distance at the given air temperature
+    pub fn as_pulse_at(&self, temperature: Temperature) -> Distance<PulseDuration> {
+        let pulse = self.0 as f32 * temperature.pulse_per_cm() * 2.54;
+        Distance(libm::roundf(pulse) as u32, PhantomData)
+    }
 }
 
 impl<U: ValidUnit> fmt::Display for Distance<U> {
@@ -0,0 +1,226 @@
+use crate::angle_unit::Radians;
+use crate::mpu6050::wrap;
+use crate::Angle;
+use core::f32::consts;
+
+/// Tuning for the heading-hold controller
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct HeadingConfig {
+    /// Proportional gain, in wheel speed units per radian of heading error
+    pub kp: f32,
+    /// Integral gain, in wheel speed units per radian-second of heading error
+    pub ki: f32,
+    /// Derivative gain, in wheel speed units per radian per second of yaw change
+    pub kd: f32,
+    /// Largest turning correction, in wheel speed units
+    pub output_limit: f32,
+    /// Weight (0-1] of each new derivative in its moving average. Lower values smooth more.
+    pub derivative_filter: f32,
+    /// Heading error treated as zero, so small wobbles don't cause corrections
+    pub deadband: Angle<Radians>,
+}
+
+impl Default for HeadingConfig {
+    fn default() -> Self {
+        Self {
+            kp: 10.0 * (180.0 / consts::PI),
+            ki: 0.0,
+            kd: 0.0,
+            output_limit: 50.0,
+            derivative_filter: 0.2,
+            deadband: Angle::new(0.25 * (consts::PI / 180.0)),
+        }
+    }
+}
+
+/// Snapshot of the heading-hold controller after an update, for logging and tuning
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct HeadingState {
+    /// Heading error (rad) after the deadband, positive when the target is to the left
+    pub error: f32,
+    /// Contributions of each term
+    pub p: f32,
+    pub i: f32,
+    pub d: f32,
+    /// Turning correction after clamping, positive turns left
+    pub output: f32,
+    /// Whether the output was clamped
+    pub saturated: bool,
+}
+
+/// PID controller holding a heading
+pub(crate) struct HeadingController {
+    config: HeadingConfig,
+    integral: f32,
+    derivative: f32,
+    prev_yaw: Option<f32>,
+    /// Heading being held
+    target: Option<f32>,
+}
+
+impl HeadingController {
+    pub(crate) fn new(config: HeadingConfig) -> Self {
+        Self {
+            config,
+            integral: 0.0,
+            derivative: 0.0,
+            prev_yaw: None,
+            target: None,
+        }
+    }
+
+    pub(crate) fn config(&self) -> HeadingConfig {
+        self.config
+    }
+
+    /// Gains can change while running, the integral keeps its contribution to the output
+    pub(crate) fn set_config(&mut self, config: HeadingConfig) {
+        self.config = config;
+    }
+
+    /// Move the heading being held without resetting the controller, for a target that moves a
+    /// little every tick, such as a wall follower's. The integral and derivative carry over.
+    pub(crate) fn retarget(&mut self, target: f32) {
+        self.target = Some(target);
+    }
+
+    /// Returns the turning correction to hold `target` from `yaw`, `dt` (us) after the last
+    /// update. Yaw (rad) must increase when turning left. A new target resets the controller.
+    pub(crate) fn update(&mut self, yaw: f32, target: f32, dt: u32) -> HeadingState {
+        if self.target != Some(target) {
+            // The integral built up for the old heading would make the robot overshoot
+            *self = Self::new(self.config);
+            self.target = Some(target);
+        }
+        let config = self.config;
+        let mut error = wrap(target - yaw);
+        if libm::fabsf(error) <= config.deadband.value() {
+            error = 0.0;
+        }
+
+        let p = config.kp * error;
+        let dt = dt as f32 / 1_000_000.0;
+
+        // Derivative of the yaw rather than the error, so a new target doesn't kick
+        if dt > 0.0 {
+            let rate = self.prev_yaw.map_or(0.0, |prev| wrap(yaw - prev) / dt);
+            self.derivative += config.derivative_filter * (-config.kd * rate - self.derivative);
+        }
+        self.prev_yaw = Some(yaw);
+        let d = self.derivative;
+
+        let integral = (self.integral + config.ki * error * dt)
+            .clamp(-config.output_limit, config.output_limit);
+        let unclamped = p + integral + d;
+        let output = unclamped.clamp(-config.output_limit, config.output_limit);
+        let saturated = output != unclamped;
+        // Anti-windup: only integrate when it doesn't push further into saturation
+        if !saturated || unclamped.signum() != error.signum() {
+            self.integral = integral;
+        }
+
+        HeadingState {
+            error,
+            p,
+            i: self.integral,
+            d,
+            output,
+            saturated,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: u32 = 10_000;
+
+    fn config() -> HeadingConfig {
+        HeadingConfig {
+            kp: 100.0,
+            ki: 50.0,
+            kd: 0.0,
+            output_limit: 50.0,
+            derivative_filter: 1.0,
+            deadband: Angle::new(0.01),
+        }
+    }
+
+    #[test]
+    fn output_is_clamped() {
+        let mut heading = HeadingController::new(config());
+        let state = heading.update(0.0, 1.0, DT);
+        assert_eq!(state.p, 100.0);
+        assert_eq!(state.output, 50.0);
+        assert!(state.saturated);
+
+        let state = heading.update(0.0, -1.0, DT);
+        assert_eq!(state.output, -50.0);
+        assert!(state.saturated);
+    }
+
+    #[test]
+    fn integral_stops_growing_while_saturated() {
+        let mut heading = HeadingController::new(HeadingConfig {
+            ki: 500.0,
+            ..config()
+        });
+        // Small enough that P alone doesn't saturate
+        let mut last = 0.0;
+        for _ in 0..100 {
+            let state = heading.update(0.0, 0.2, DT);
+            assert!(state.i >= last);
+            last = state.i;
+            if state.saturated {
+                break;
+            }
+        }
+        assert!(last > 0.0);
+        // Stuck against the limit, the integral holds rather than winding up
+        for _ in 0..100 {
+            let state = heading.update(0.0, 0.2, DT);
+            assert!(state.saturated);
+            assert_eq!(state.i, last);
+        }
+        // Overshooting unwinds it straight away
+        let state = heading.update(0.25, 0.2, DT);
+        assert!(!state.saturated);
+        assert!(state.i < last);
+    }
+
+    #[test]
+    fn integral_and_derivative_scale_with_time() {
+        let config = HeadingConfig {
+            kp: 0.0,
+            ki: 10.0,
+            kd: 2.0,
+            ..config()
+        };
+        let mut fast = HeadingController::new(config);
+        let mut slow = HeadingController::new(config);
+        let fast_state = fast.update(0.0, 0.5, DT);
+        let slow_state = slow.update(0.0, 0.5, 2 * DT);
+        // The same error held for twice as long adds twice as much
+        assert!((fast_state.i - 0.05).abs() < 1e-6);
+        assert!((slow_state.i - 0.1).abs() < 1e-6);
+
+        // Same turning rate (rad/s), measured over different update periods
+        let fast_state = fast.update(0.01, 0.5, DT);
+        let slow_state = slow.update(0.02, 0.5, 2 * DT);
+        assert!((fast_state.d + 2.0).abs() < 1e-3);
+        assert!((slow_state.d + 2.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn errors_within_the_deadband_are_ignored() {
+        let mut heading = HeadingController::new(config());
+        for _ in 0..10 {
+            let state = heading.update(0.0, 0.009, DT);
+            assert_eq!(state.error, 0.0);
+            assert_eq!(state.output, 0.0);
+        }
+        let state = heading.update(0.0, 0.02, DT);
+        assert!(state.output > 0.0);
+    }
+}
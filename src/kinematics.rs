@@ -0,0 +1,152 @@
+use crate::servo::{InputRange, ServoRanges};
+
+/// How much a wheel's speed changes with the robot's linear and angular velocity
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct WheelModel {
+    /// Wheel speed per unit of forward velocity
+    pub linear: f32,
+    /// Wheel speed per unit of counter-clockwise (left) angular velocity
+    pub angular: f32,
+}
+
+/// Kinematic model of the three-wheel layout, mapping robot velocities to wheel speeds.
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct Kinematics {
+    pub front: WheelModel,
+    pub left: WheelModel,
+    pub right: WheelModel,
+}
+
+impl Kinematics {
+    /// Fastest wheel speed, in either direction
+    const MAX_SPEED: f32 = InputRange::CONTINUOUS_RANGE.1 as f32;
+
+    /// Returns the front, left and right wheel speeds for a linear and angular velocity.
+    ///
+    /// If any wheel would exceed the maximum speed, all wheels are slowed by the same factor.
+    /// This keeps the ratio of angular to linear velocity, so the robot still follows the
+    /// same curve, just slower.
+    pub fn wheel_speeds(&self, linear: f32, angular: f32) -> (f32, f32, f32) {
+        let speed = |wheel: &WheelModel| wheel.linear * linear + wheel.angular * angular;
+        let (front, left, right) = (speed(&self.front), speed(&self.left), speed(&self.right));
+
+        let fastest = libm::fabsf(front)
+            .max(libm::fabsf(left))
+            .max(libm::fabsf(right));
+        if fastest > Self::MAX_SPEED {
+            let scale = Self::MAX_SPEED / fastest;
+            (front * scale, left * scale, right * scale)
+        } else {
+            (front, left, right)
+        }
+    }
+
+    /// Returns the linear and angular velocity for the left and right wheel speeds.
+    /// The front wheel is ignored, it only follows the other two.
+    pub fn velocities(&self, left: f32, right: f32) -> (f32, f32) {
+        // Invert the 2x2 model of the side wheels
+        let (l, r) = (self.left, self.right);
+        let determinant = l.linear * r.angular - l.angular * r.linear;
+        if determinant == 0.0 {
+            return (0.0, 0.0);
+        }
+        let linear = (left * r.angular - right * l.angular) / determinant;
+        let angular = (right * l.linear - left * r.linear) / determinant;
+        (linear, angular)
+    }
+}
+
+impl Default for Kinematics {
+    /// The front wheel only drives forward, the side wheels differ to turn.
+    fn default() -> Self {
+        Self {
+            front: WheelModel {
+                linear: 1.0,
+                angular: 0.0,
+            },
+            left: WheelModel {
+                linear: 1.0,
+                angular: -1.0,
+            },
+            right: WheelModel {
+                linear: 1.0,
+                angular: 1.0,
+            },
+        }
+    }
+}
+
+/// Ground speed of the robot for a wheel speed command, measured by timing straight runs.
+/// Above `offset` the speed rises linearly with the command.
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct SpeedModel {
+    /// Speed (mm/s) gained per wheel speed unit above the offset
+    pub mm_per_s: f32,
+    /// Smallest wheel speed that moves the robot
+    pub offset: f32,
+}
+
+impl SpeedModel {
+    /// Fit the model to two timed runs, each a wheel speed and the ground speed (mm/s) it
+    /// gave. Use one slow and one fast run for best accuracy.
+    ///
+    /// Returns `None` unless the faster command gave a higher speed, e.g. for two runs at the
+    /// same command or a robot that didn't move.
+    pub fn from_runs(slow: (f32, f32), fast: (f32, f32)) -> Option<Self> {
+        let mm_per_s = (fast.1 - slow.1) / (fast.0 - slow.0);
+        if !mm_per_s.is_finite() || mm_per_s <= 0.0 {
+            return None;
+        }
+        Some(Self {
+            mm_per_s,
+            offset: slow.0 - slow.1 / mm_per_s,
+        })
+    }
+
+    /// Ground speed (mm/s) for a wheel speed command
+    pub fn speed(&self, command: f32) -> f32 {
+        let magnitude = (libm::fabsf(command) - self.offset).max(0.0);
+        libm::copysignf(magnitude * self.mm_per_s, command)
+    }
+
+    /// Wheel speed command for a ground speed (mm/s)
+    pub fn command(&self, speed: f32) -> f32 {
+        if speed == 0.0 {
+            return 0.0;
+        }
+        libm::copysignf(self.offset + libm::fabsf(speed) / self.mm_per_s, speed)
+    }
+}
+
+impl Default for SpeedModel {
+    /// Rough figures for the stock wheels. Measure your own robot for accurate distances.
+    fn default() -> Self {
+        Self {
+            mm_per_s: 2.0,
+            offset: 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_model_fits_runs() {
+        let model = SpeedModel::from_runs((20.0, 30.0), (60.0, 130.0)).unwrap();
+        assert_eq!(model.mm_per_s, 2.5);
+        assert_eq!(model.offset, 8.0);
+        assert_eq!(model.speed(model.command(-100.0)), -100.0);
+    }
+
+    #[test]
+    fn speed_model_rejects_degenerate_runs() {
+        // Same command
+        assert!(SpeedModel::from_runs((40.0, 80.0), (40.0, 90.0)).is_none());
+        // Same speed
+        assert!(SpeedModel::from_runs((20.0, 0.0), (60.0, 0.0)).is_none());
+        // Slower at the faster command
+        assert!(SpeedModel::from_runs((20.0, 90.0), (60.0, 40.0)).is_none());
+    }
+}
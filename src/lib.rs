@@ -13,20 +13,93 @@ mod usart;
 pub use usart::Usart;
 
 mod servo;
+pub use servo::ChannelServo;
 pub use servo::InputRange;
+pub use servo::IntoChannelServos;
+pub use servo::MotionProfile;
 pub use servo::Servo;
+pub use servo::ServoBank;
+pub use servo::ServoCalibration;
+pub use servo::ServoOutput;
 pub use servo::ServoRanges;
+pub use servo::ServoStates;
+
+mod arbiter;
+pub use arbiter::CommandArbiter;
+pub use arbiter::MotionCommand;
+pub use arbiter::Priority;
+
+mod autotune;
+pub use autotune::AutotuneStatus;
+pub use autotune::PidGains;
+pub use autotune::RelayAutotune;
+pub use autotune::UltimateGain;
+
+mod encoder;
+pub use encoder::Encoder;
+pub use encoder::ExtiCounter;
+pub use encoder::PulseCounter;
+pub use encoder::SpeedControlGains;
+use encoder::WheelSpeedController;
+
+mod heading;
+pub use heading::HeadingConfig;
+use heading::HeadingController;
+pub use heading::HeadingState;
+
+mod kinematics;
+pub use kinematics::Kinematics;
+pub use kinematics::SpeedModel;
+pub use kinematics::WheelModel;
+
+mod motion;
+pub use motion::ProfileError;
+pub use motion::TrapezoidalProfile;
+
+mod odometry;
+pub use odometry::Odometry;
+pub use odometry::Pose;
+
+mod stall;
+pub use stall::StallConfig;
+pub use stall::StallDetector;
+pub use stall::StallStatus;
+
+mod turn;
+use turn::Turn;
+pub use turn::TurnConfig;
+pub use turn::TurnStatus;
 
 mod ultrasonic;
 pub use ultrasonic::unit as distance_unit;
+pub use ultrasonic::ClockError;
+pub use ultrasonic::Clocked;
+pub use ultrasonic::EchoClock;
+pub use ultrasonic::EchoTiming;
+pub use ultrasonic::RangeError;
+pub use ultrasonic::RangeSensor;
+pub use ultrasonic::Temperature;
 pub use ultrasonic::Ultrasonic;
 
+mod ultrasonic_scheduler;
+pub use ultrasonic_scheduler::UltrasonicScheduler;
+
+pub mod filter;
+pub use filter::RangeFilter;
+
+mod wall_follow;
+pub use wall_follow::Side;
+pub use wall_follow::WallFollow;
+pub use wall_follow::WallFollowConfig;
+pub use wall_follow::WallFollowState;
+
 mod vl53l1x;
 pub use vl53l1x::Vl53l1x;
 
 mod mpu6050;
 pub use mpu6050::unit as angle_unit;
 pub use mpu6050::Angle;
+pub use mpu6050::ImuMotion;
 pub use mpu6050::Mpu6050;
 pub use mpu6050::YawPitchRoll;
 
@@ -35,15 +108,18 @@ pub use hc_sr04::HcSr04;
 
 pub mod urm37;
 pub use urm37::Urm37;
+pub use urm37::Urm37Serial;
 
 use core::f32::consts;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
 use hal::{
     gpio::{Alternate, Pin},
+    nb,
+    prelude::*,
     rcc::Clocks,
-    timer::Ch,
+    timer::{Ch, CounterUs, Instance},
 };
 use pac::{TIM1, TIM3, TIM5};
-use pid::Pid;
 use stm32f4xx_hal::gpio::{PA1, PA11, PC6};
 
 pub struct MikotoWheels {
@@ -59,11 +135,163 @@ pub struct MikotoPeripherals {
     pub wheels: MikotoWheels,
 }
 
-pub struct Mikoto {
-    front_wheel: Servo<TIM3, Pin<'C', 6, Alternate<2>>, Ch<0>>,
-    left_wheel: Servo<TIM1, Pin<'A', 11, Alternate<1>>, Ch<3>>,
-    right_wheel: Servo<TIM5, Pin<'A', 1, Alternate<2>>, Ch<1>>,
-    pid: Pid<f32>,
+/// Front wheel output of the stock board
+pub type FrontWheel = Servo<TIM3, Pin<'C', 6, Alternate<2>>, Ch<0>>;
+/// Left wheel output of the stock board
+pub type LeftWheel = Servo<TIM1, Pin<'A', 11, Alternate<1>>, Ch<3>>;
+/// Right wheel output of the stock board
+pub type RightWheel = Servo<TIM5, Pin<'A', 1, Alternate<2>>, Ch<1>>;
+
+/// The robot, driving its front, left and right wheels through any three servo outputs.
+/// Defaults to the stock board's outputs.
+pub struct Mikoto<F = FrontWheel, L = LeftWheel, R = RightWheel> {
+    front_wheel: F,
+    left_wheel: L,
+    right_wheel: R,
+    heading: HeadingController,
+    kinematics: Kinematics,
+    turn: Turn,
+    acceleration_limit: Option<f32>,
+    speed_model: SpeedModel,
+    odometry: Odometry,
+    speed_control: Option<SpeedControlGains>,
+    /// Front, left and right speed commands before speed control
+    commands: [i32; 3],
+    speed_controllers: [WheelSpeedController; 3],
+}
+
+/// Gains and limits for Mikoto's controllers, so they can be tuned without reflashing
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MikotoConfig {
+    /// Heading hold used by `drive_straight`
+    pub heading: HeadingConfig,
+    /// Turning on the spot with `turn_to`
+    pub turn: TurnConfig,
+    /// Mapping from robot velocities to wheel speeds
+    pub kinematics: Kinematics,
+    /// Wheel acceleration limit, in speed units per second
+    pub acceleration_limit: Option<f32>,
+    /// Ground speed for a wheel speed, used to drive motion profiles
+    pub speed_model: SpeedModel,
+    /// Closed-loop wheel speed control with `regulate_speed`
+    pub speed_control: Option<SpeedControlGains>,
+}
+
+/// Progress of `Mikoto::drive_profile`
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub enum MoveStatus {
+    /// Still moving, with the heading controller's state for logging
+    Moving(HeadingState),
+    /// Reached the end of the profile and stopped
+    Done,
+}
+
+/// Builds a `Mikoto` from any three servo outputs, for chassis that differ from the stock one
+pub struct MikotoBuilder<F, L, R> {
+    front_wheel: F,
+    left_wheel: L,
+    right_wheel: R,
+    /// Front, left and right wheels mounted so positive speeds spin them backward
+    reversed: [bool; 3],
+    /// Front, left and right pulse widths (us), if not the servos' own
+    pulses: [Option<(u32, u32)>; 3],
+    config: MikotoConfig,
+}
+
+impl<F: ServoOutput, L: ServoOutput, R: ServoOutput> MikotoBuilder<F, L, R> {
+    pub fn new(front_wheel: F, left_wheel: L, right_wheel: R) -> Self {
+        Self {
+            front_wheel,
+            left_wheel,
+            right_wheel,
+            reversed: [false; 3],
+            pulses: [None; 3],
+            config: MikotoConfig::default(),
+        }
+    }
+
+    /// Reverse a wheel mounted so that positive speeds would spin it backward
+    pub fn reversed(mut self, wheel: Wheel, reversed: bool) -> Self {
+        self.reversed[wheel as usize] = reversed;
+        self
+    }
+
+    /// Pulse widths (us) at either end of a wheel's speed range
+    pub fn pulse(mut self, wheel: Wheel, min_pulse: u32, max_pulse: u32) -> Self {
+        self.pulses[wheel as usize] = Some((min_pulse, max_pulse));
+        self
+    }
+
+    /// Wheel geometry, mapping robot velocities to wheel speeds
+    pub fn kinematics(mut self, kinematics: Kinematics) -> Self {
+        self.config.kinematics = kinematics;
+        self
+    }
+
+    /// Gains and limits. Replaces any kinematics set before.
+    pub fn config(mut self, config: MikotoConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn build(mut self) -> Result<Mikoto<F, L, R>, servo::Error> {
+        setup_wheel(
+            &mut self.front_wheel,
+            self.reversed[Wheel::Front as usize],
+            self.pulses[Wheel::Front as usize],
+        )?;
+        setup_wheel(
+            &mut self.left_wheel,
+            self.reversed[Wheel::Left as usize],
+            self.pulses[Wheel::Left as usize],
+        )?;
+        setup_wheel(
+            &mut self.right_wheel,
+            self.reversed[Wheel::Right as usize],
+            self.pulses[Wheel::Right as usize],
+        )?;
+
+        let config = self.config;
+        let mut mikoto = Mikoto {
+            front_wheel: self.front_wheel,
+            left_wheel: self.left_wheel,
+            right_wheel: self.right_wheel,
+            heading: HeadingController::new(config.heading),
+            kinematics: config.kinematics,
+            turn: Turn::new(config.turn),
+            acceleration_limit: None,
+            speed_model: config.speed_model,
+            odometry: Odometry::new(),
+            speed_control: config.speed_control,
+            commands: [0; 3],
+            speed_controllers: Default::default(),
+        };
+        mikoto.set_acceleration_limit(config.acceleration_limit);
+        Ok(mikoto)
+    }
+}
+
+/// Sets a wheel's pulse widths and a continuous input range in its direction
+fn setup_wheel<S: ServoOutput>(
+    wheel: &mut S,
+    reversed: bool,
+    pulse: Option<(u32, u32)>,
+) -> Result<(), servo::Error> {
+    if let Some((min_pulse, max_pulse)) = pulse {
+        wheel.set_pulse(min_pulse, max_pulse)?;
+    }
+    if reversed {
+        wheel.set_input_range(InputRange::CONTINUOUS_RANGE.rev())
+    } else {
+        wheel.set_input_range(InputRange::CONTINUOUS_RANGE)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, defmt::Format)]
+pub enum Wheel {
+    Front,
+    Left,
+    Right,
 }
 
 #[derive(Copy, Clone)]
@@ -130,47 +358,113 @@ impl Direction {
 
 impl Mikoto {
     pub fn new(dp: MikotoPeripherals, clocks: &Clocks) -> Self {
-        let mut front_wheel = Servo::new(
-            500.0,
-            2500.0,
+        Self::with_config(dp, clocks, MikotoConfig::default())
+    }
+
+    pub fn with_config(dp: MikotoPeripherals, clocks: &Clocks, config: MikotoConfig) -> Self {
+        let front_wheel = Servo::new(
+            500,
+            2500,
             dp.wheels.pc6.into_alternate(),
             dp.wheels.tim3,
             clocks,
         )
         .unwrap();
-        let mut left_wheel = Servo::new(
-            500.0,
-            2500.0,
+        let left_wheel = Servo::new(
+            500,
+            2500,
             dp.wheels.pa11.into_alternate(),
             dp.wheels.tim1,
             clocks,
         )
         .unwrap();
-        let mut right_wheel = Servo::new(
-            500.0,
-            2500.0,
+        let right_wheel = Servo::new(
+            500,
+            2500,
             dp.wheels.pa1.into_alternate(),
             dp.wheels.tim5,
             clocks,
         )
         .unwrap();
 
-        front_wheel.set_input_range(InputRange::CONTINUOUS_RANGE.rev());
-        left_wheel.set_input_range(InputRange::CONTINUOUS_RANGE);
-        right_wheel.set_input_range(InputRange::CONTINUOUS_RANGE.rev());
+        MikotoBuilder::new(front_wheel, left_wheel, right_wheel)
+            .reversed(Wheel::Front, true)
+            .reversed(Wheel::Right, true)
+            .config(config)
+            .build()
+            .unwrap()
+    }
+}
 
-        let mut pid = Pid::new(0.0, 25.0);
-        pid.p(10.0 * (180.0 / consts::PI), 25.0);
-        // pid.p(4.0 * (180.0 / consts::PI), 25.0);
-        // pid.i(10.0 * (180.0 / consts::PI), 25.0);
-        // pid.d(150.0 * (180.0 / consts::PI), 25.0);
+impl<F: ServoOutput, L: ServoOutput, R: ServoOutput> Mikoto<F, L, R> {
+    /// Start building a robot with other wheel outputs, reversal, pulse widths or geometry
+    pub fn builder(front_wheel: F, left_wheel: L, right_wheel: R) -> MikotoBuilder<F, L, R> {
+        MikotoBuilder::new(front_wheel, left_wheel, right_wheel)
+    }
 
-        Self {
-            front_wheel,
-            left_wheel,
-            right_wheel,
-            pid,
+    pub fn config(&self) -> MikotoConfig {
+        MikotoConfig {
+            heading: self.heading.config(),
+            turn: self.turn.config(),
+            kinematics: self.kinematics,
+            acceleration_limit: self.acceleration_limit,
+            speed_model: self.speed_model,
+            speed_control: self.speed_control,
+        }
+    }
+
+    /// Replace all gains and limits. Resets the controllers and abandons a turn in progress.
+    pub fn set_config(&mut self, config: MikotoConfig) {
+        self.heading = HeadingController::new(config.heading);
+        self.turn.set_config(config.turn);
+        self.kinematics = config.kinematics;
+        self.speed_model = config.speed_model;
+        self.set_speed_control(config.speed_control);
+        self.set_acceleration_limit(config.acceleration_limit);
+    }
+
+    /// Set the heading hold gains used by `drive_straight`. Takes effect on the next update.
+    pub fn set_heading_gains(&mut self, kp: f32, ki: f32, kd: f32) {
+        self.heading.set_config(HeadingConfig {
+            kp,
+            ki,
+            kd,
+            ..self.heading.config()
+        });
+    }
+
+    /// Run a relay autotune of the heading controller by turning left and right on the spot.
+    /// Call every tick with the current yaw, which must increase when turning left, and the
+    /// time (us) since the last tick. Stops the wheels once finished.
+    ///
+    /// Apply the proposed gains with `set_heading_gains`.
+    pub fn autotune_heading(
+        &mut self,
+        autotune: &mut RelayAutotune,
+        current_yaw: Angle<angle_unit::Radians>,
+        dt: u32,
+    ) -> Result<AutotuneStatus, servo::Error> {
+        let status = autotune.update(current_yaw.value(), dt);
+        match status {
+            AutotuneStatus::InProgress => {
+                // Through the kinematics, like the heading controller's output, so the
+                // measured gain matches the loop being tuned
+                let relay = autotune.relay() as f32;
+                let angular = if autotune.turning_left() {
+                    relay
+                } else {
+                    -relay
+                };
+                self.drive_velocity(0.0, angular)?
+            }
+            _ => self.stop()?,
         }
+        Ok(status)
+    }
+
+    /// Set the heading hold gains, limits and deadband used by `drive_straight`.
+    pub fn set_heading_config(&mut self, config: HeadingConfig) {
+        self.heading.set_config(config);
     }
 
     pub fn drive(&mut self, direction: Direction, speed: u32) -> Result<(), servo::Error> {
@@ -185,50 +479,718 @@ impl Mikoto {
         }
 
         let (front_speed, left_speed, right_speed) = direction.motor_direction(speed);
+        self.set_wheel_speeds(front_speed, left_speed, right_speed)
+    }
+
+    /// Drive with a forward `linear` velocity while turning counter-clockwise (left) at an
+    /// `angular` velocity, both in wheel speed units. Negative values reverse and turn right.
+    ///
+    /// Wheel speeds come from the kinematic model. When a wheel would exceed full speed,
+    /// all wheels slow down together so the robot keeps the same curvature.
+    pub fn drive_velocity(&mut self, linear: f32, angular: f32) -> Result<(), servo::Error> {
+        let (front, left, right) = self.kinematics.wheel_speeds(linear, angular);
+        self.set_wheel_speeds(
+            libm::roundf(front) as i32,
+            libm::roundf(left) as i32,
+            libm::roundf(right) as i32,
+        )
+    }
+
+    /// Drive with the winning command of `arbiter`, or stop if every command has expired.
+    /// Call every tick with the time (us) since the last tick.
+    ///
+    /// Returns the priority in control, if any.
+    pub fn drive_arbitrated(
+        &mut self,
+        arbiter: &mut CommandArbiter,
+        dt: u32,
+    ) -> Result<Option<Priority>, servo::Error> {
+        match arbiter.update(dt) {
+            Some((priority, command)) => {
+                self.drive_command(command)?;
+                Ok(Some(priority))
+            }
+            None => {
+                self.stop()?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Set the model mapping robot velocities to wheel speeds for `drive_velocity`.
+    pub fn set_kinematics(&mut self, kinematics: Kinematics) {
+        self.kinematics = kinematics;
+    }
 
-        self.front_wheel.set_position(front_speed)?;
-        self.left_wheel.set_position(left_speed)?;
-        self.right_wheel.set_position(right_speed)?;
+    pub fn kinematics(&self) -> Kinematics {
+        self.kinematics
+    }
+
+    /// Turn on the spot toward `target_yaw`. Call every tick with the current yaw, which must
+    /// increase when turning left (counter-clockwise), and the time (us) since the last tick.
+    ///
+    /// Stops the wheels once the robot has settled within tolerance of the target, or when
+    /// the turn times out, and keeps returning that status while the target is unchanged.
+    /// Passing a different target starts a new turn.
+    pub fn turn_to(
+        &mut self,
+        current_yaw: Angle<angle_unit::Radians>,
+        target_yaw: Angle<angle_unit::Radians>,
+        dt: u32,
+    ) -> Result<TurnStatus, servo::Error> {
+        let (command, status) = self.turn_command(current_yaw, target_yaw, dt);
+        self.drive_command(command)?;
+        Ok(status)
+    }
+
+    /// Like `turn_to`, but return the command instead of driving the wheels, e.g. to publish
+    /// it to a `CommandArbiter`. The command is `MotionCommand::STOP` once the turn ends.
+    pub fn turn_command(
+        &mut self,
+        current_yaw: Angle<angle_unit::Radians>,
+        target_yaw: Angle<angle_unit::Radians>,
+        dt: u32,
+    ) -> (MotionCommand, TurnStatus) {
+        let (angular, status) = self
+            .turn
+            .update(current_yaw.value(), target_yaw.value(), dt);
+        match status {
+            TurnStatus::InProgress => (MotionCommand::new(0.0, angular), status),
+            _ => (MotionCommand::STOP, status),
+        }
+    }
+
+    /// Set the gains, tolerance and timing used by `turn_to`. Abandons the turn in progress.
+    pub fn set_turn_config(&mut self, config: TurnConfig) {
+        self.turn.set_config(config);
+    }
+
+    pub fn turn_config(&self) -> TurnConfig {
+        self.turn.config()
+    }
+
+    fn set_wheel_speeds(&mut self, front: i32, left: i32, right: i32) -> Result<(), servo::Error> {
+        self.front_wheel.set_target(front)?;
+        self.left_wheel.set_target(left)?;
+        self.right_wheel.set_target(right)?;
+        self.commands = [front, left, right];
+        // Resume after coasting
+        self.front_wheel.attach();
+        self.left_wheel.attach();
+        self.right_wheel.attach();
         Ok(())
     }
 
+    /// Limit wheel acceleration to avoid wheel slip and current spikes, in speed units per
+    /// second. Speed changes are then applied gradually by `update`. `None` removes the limit.
+    pub fn set_acceleration_limit(&mut self, limit: Option<f32>) {
+        self.acceleration_limit = limit;
+        self.front_wheel.set_slew_rate(limit);
+        self.left_wheel.set_slew_rate(limit);
+        self.right_wheel.set_slew_rate(limit);
+    }
+
+    /// Enable closed-loop wheel speed control with `gains`, or disable it with `None`.
+    pub fn set_speed_control(&mut self, gains: Option<SpeedControlGains>) {
+        self.speed_control = gains;
+        for controller in &mut self.speed_controllers {
+            controller.reset();
+        }
+    }
+
+    /// Correct the wheel speeds from their `measured` speeds (mm/s), in front, left, right
+    /// order, usually read from encoders. Call every tick after driving, with the time (us)
+    /// since the last tick. Does nothing unless speed control is enabled.
+    ///
+    /// Each measured speed is positive when its wheel drives the robot the way a positive
+    /// speed does, whether or not the wheel is mounted reversed. Negate the readings of
+    /// encoders on reversed wheels if they count down when driving forward.
+    ///
+    /// Each wheel's target speed comes from its command through the speed model.
+    pub fn regulate_speed(&mut self, measured: [f32; 3], dt: u32) -> Result<(), servo::Error> {
+        let Some(gains) = self.speed_control else {
+            return Ok(());
+        };
+        let max = InputRange::CONTINUOUS_RANGE.1 as f32;
+        let mut corrected = [0; 3];
+        for (wheel, output) in corrected.iter_mut().enumerate() {
+            let command = self.commands[wheel];
+            let target = self.speed_model.speed(command as f32);
+            let speed =
+                self.speed_controllers[wheel].update(gains, command, target, measured[wheel], dt);
+            *output = libm::roundf(speed.clamp(-max, max)) as i32;
+        }
+        self.front_wheel.set_target(corrected[0])?;
+        self.left_wheel.set_target(corrected[1])?;
+        self.right_wheel.set_target(corrected[2])?;
+        Ok(())
+    }
+
+    /// Ramp wheel speeds toward their targets and end a timed brake. `dt` is the time (us)
+    /// since the last update.
+    pub fn update(&mut self, dt: u32) {
+        self.front_wheel.update(dt);
+        self.left_wheel.update(dt);
+        self.right_wheel.update(dt);
+    }
+
+    /// Drive in `direction` while holding the heading `desired_angle`. Call every tick with
+    /// the current yaw, which must increase when turning left (counter-clockwise), and the
+    /// time (us) since the last tick.
+    ///
+    /// Returns the heading controller's state for logging.
     pub fn drive_straight(
         &mut self,
         current_yaw: Angle<angle_unit::Radians>,
         desired_angle: Angle<angle_unit::Radians>,
         direction: Direction,
         speed: u32,
-    ) -> Result<(), servo::Error> {
-        let output = self
-            .pid
-            .next_control_output(current_yaw.value() - desired_angle.value())
-            .output;
-        if output < -0.5f32 {
-            // offset right
-            self.drive(
-                Direction::VeerLeft {
-                    direction: VeerOptions::try_from(direction)?,
-                    percentage: 65 + (-1.0 * output) as u32,
-                },
-                speed,
-            )?;
-        } else if output > 0.5f32 {
-            // offset left
-            self.drive(
-                Direction::VeerRight {
-                    direction: VeerOptions::try_from(direction)?,
-                    percentage: 75 + (output) as u32,
-                },
-                speed,
-            )?;
+        dt: u32,
+    ) -> Result<HeadingState, servo::Error> {
+        let (command, heading) =
+            self.straight_command(current_yaw, desired_angle, direction, speed, dt)?;
+        self.drive_command(command)?;
+        Ok(heading)
+    }
+
+    /// Like `drive_straight`, but return the command instead of driving the wheels, e.g. to
+    /// publish it to a `CommandArbiter`. Only `Forward` and `Backward` are valid directions.
+    pub fn straight_command(
+        &mut self,
+        current_yaw: Angle<angle_unit::Radians>,
+        desired_angle: Angle<angle_unit::Radians>,
+        direction: Direction,
+        speed: u32,
+        dt: u32,
+    ) -> Result<(MotionCommand, HeadingState), servo::Error> {
+        let linear = VeerOptions::try_from(direction)? as i32 * speed as i32;
+        Ok(self.heading_command(current_yaw, desired_angle, linear as f32, dt))
+    }
+
+    /// Drive forward at `speed` alongside a wall, reading its distance from the side `sensor`.
+    /// Call every tick with the current yaw, which must increase when turning left
+    /// (counter-clockwise), and the time (us) since the last tick.
+    ///
+    /// The sensor measures in the background without holding up the tick, so ultrasonic
+    /// sensors need their echo interrupt set up (see `EchoTiming`), and are passed along with
+    /// their clock as `Clocked`. Steers by the latest finished measurement, which `wall` holds
+    /// between ticks.
+    ///
+    /// Returns the wall follower's estimate and the heading controller's state for logging.
+    pub fn follow_wall<S: RangeSensor>(
+        &mut self,
+        wall: &mut WallFollow,
+        sensor: &mut S,
+        current_yaw: Angle<angle_unit::Radians>,
+        speed: u32,
+        dt: u32,
+    ) -> Result<(WallFollowState, HeadingState), servo::Error> {
+        let (command, wall_state, heading) =
+            self.wall_command(wall, sensor, current_yaw, speed, dt);
+        self.drive_command(command)?;
+        Ok((wall_state, heading))
+    }
+
+    /// Like `follow_wall`, but return the command instead of driving the wheels, e.g. to
+    /// publish it to a `CommandArbiter`.
+    pub fn wall_command<S: RangeSensor>(
+        &mut self,
+        wall: &mut WallFollow,
+        sensor: &mut S,
+        current_yaw: Angle<angle_unit::Radians>,
+        speed: u32,
+        dt: u32,
+    ) -> (MotionCommand, WallFollowState, HeadingState) {
+        match sensor.poll_mm() {
+            Ok(range) => wall.set_range(Some(range)),
+            Err(nb::Error::Other(_)) => wall.set_range(None),
+            Err(nb::Error::WouldBlock) => {}
+        }
+        // Only fails with `WouldBlock` until the sensor is ready for the next measurement
+        sensor.start().ok();
+        let wall_state = wall.update(wall.range(), current_yaw);
+        // The heading moves with every reading, which must not reset the controller
+        self.heading.retarget(wall_state.heading.value());
+        let (command, heading) =
+            self.heading_command(current_yaw, wall_state.heading, speed as f32, dt);
+        (command, wall_state, heading)
+    }
+
+    /// Drive along `profile` while holding the heading `desired_angle`. Call every tick with
+    /// the current yaw, which must increase when turning left, and the time (us) since the
+    /// last tick. Stops at the end of the profile.
+    ///
+    /// Profile speeds are converted to wheel speeds with the speed model.
+    pub fn drive_profile(
+        &mut self,
+        profile: &mut TrapezoidalProfile,
+        current_yaw: Angle<angle_unit::Radians>,
+        desired_angle: Angle<angle_unit::Radians>,
+        dt: u32,
+    ) -> Result<MoveStatus, servo::Error> {
+        let (command, status) = self.profile_command(profile, current_yaw, desired_angle, dt);
+        self.drive_command(command)?;
+        Ok(status)
+    }
+
+    /// Like `drive_profile`, but return the command instead of driving the wheels, e.g. to
+    /// publish it to a `CommandArbiter`. The command is `MotionCommand::STOP` once done.
+    pub fn profile_command(
+        &mut self,
+        profile: &mut TrapezoidalProfile,
+        current_yaw: Angle<angle_unit::Radians>,
+        desired_angle: Angle<angle_unit::Radians>,
+        dt: u32,
+    ) -> (MotionCommand, MoveStatus) {
+        match profile.update(dt) {
+            Some(speed) => {
+                let linear = self.speed_model.command(speed);
+                let (command, state) = self.heading_command(current_yaw, desired_angle, linear, dt);
+                (command, MoveStatus::Moving(state))
+            }
+            None => (MotionCommand::STOP, MoveStatus::Done),
+        }
+    }
+
+    /// Estimate the robot's pose from the current wheel speeds and the IMU yaw, which must
+    /// increase when turning left. Call every tick with the time (us) since the last tick.
+    pub fn update_odometry(&mut self, current_yaw: Angle<angle_unit::Radians>, dt: u32) -> Pose {
+        let (linear, _) = self.kinematics.velocities(
+            self.left_wheel.position() as f32,
+            self.right_wheel.position() as f32,
+        );
+        // Coasting wheels soon stop
+        let speed = if self.left_wheel.is_attached() {
+            self.speed_model.speed(linear)
         } else {
-            // offset fixed
-            self.drive(direction, speed)?;
+            0.0
+        };
+        self.odometry.update(speed, current_yaw, dt)
+    }
+
+    /// Linear and angular velocity the wheels are commanded to, in wheel speed units.
+    /// Zero while coasting.
+    pub fn commanded_velocity(&self) -> (f32, f32) {
+        if !self.left_wheel.is_attached() {
+            return (0.0, 0.0);
         }
-        Ok(())
+        self.kinematics
+            .velocities(self.commands[1] as f32, self.commands[2] as f32)
+    }
+
+    /// Check whether the robot is stuck despite the wheels being driven. Call every tick with
+    /// the IMU attitude and motion, the measured ground speed (mm/s) if known, e.g. from
+    /// encoders, and the time (us) since the last tick.
+    pub fn detect_stall(
+        &self,
+        detector: &mut StallDetector,
+        attitude: YawPitchRoll,
+        motion: ImuMotion,
+        measured_speed: Option<f32>,
+        dt: u32,
+    ) -> StallStatus {
+        let (linear, angular) = self.commanded_velocity();
+        detector.update(linear, angular, attitude, motion, measured_speed, dt)
+    }
+
+    /// Latest pose estimate
+    pub fn pose(&self) -> Pose {
+        self.odometry.pose()
+    }
+
+    /// Set the pose at a known landmark, along with the IMU yaw at the same moment.
+    pub fn reset_pose(&mut self, pose: Pose, current_yaw: Angle<angle_unit::Radians>) {
+        self.odometry.reset(pose, current_yaw);
+    }
+
+    fn heading_command(
+        &mut self,
+        current_yaw: Angle<angle_unit::Radians>,
+        desired_angle: Angle<angle_unit::Radians>,
+        linear: f32,
+        dt: u32,
+    ) -> (MotionCommand, HeadingState) {
+        let state = self
+            .heading
+            .update(current_yaw.value(), desired_angle.value(), dt);
+        (MotionCommand::new(linear, state.output), state)
+    }
+
+    fn drive_command(&mut self, command: MotionCommand) -> Result<(), servo::Error> {
+        self.drive_velocity(command.linear, command.angular)
     }
 
     pub fn stop(&mut self) -> Result<(), servo::Error> {
         self.drive(Direction::Forward, 0)
     }
+
+    /// Stop all wheels at once, ignoring the acceleration limit
+    fn halt(&mut self) -> Result<(), servo::Error> {
+        self.front_wheel.set_position(0)?;
+        self.left_wheel.set_position(0)?;
+        self.right_wheel.set_position(0)?;
+        self.commands = [0; 3];
+        self.front_wheel.attach();
+        self.left_wheel.attach();
+        self.right_wheel.attach();
+        Ok(())
+    }
+
+    /// Stop sending pulses to the wheels, so they spin freely and draw no holding current.
+    /// The next `drive` resumes.
+    pub fn coast(&mut self) {
+        self.front_wheel.detach();
+        self.left_wheel.detach();
+        self.right_wheel.detach();
+    }
+
+    /// Actively stop the wheels for `duration` (us), then coast. The time is counted by `update`.
+    pub fn brake(&mut self, duration: u32) {
+        self.front_wheel.brake(duration);
+        self.left_wheel.brake(duration);
+        self.right_wheel.brake(duration);
+    }
+
+    /// Set a wheel's neutral point, deadband and gain corrections.
+    pub fn set_calibration(&mut self, wheel: Wheel, calibration: ServoCalibration) {
+        match wheel {
+            Wheel::Front => self.front_wheel.set_calibration(calibration),
+            Wheel::Left => self.left_wheel.set_calibration(calibration),
+            Wheel::Right => self.right_wheel.set_calibration(calibration),
+        }
+    }
+
+    pub fn calibration(&self, wheel: Wheel) -> ServoCalibration {
+        match wheel {
+            Wheel::Front => self.front_wheel.calibration(),
+            Wheel::Left => self.left_wheel.calibration(),
+            Wheel::Right => self.right_wheel.calibration(),
+        }
+    }
+
+    /// Find a wheel's neutral point and deadband by sweeping its neutral offset while stopped
+    /// and watching the gyro for the robot turning. The robot must be on the ground.
+    ///
+    /// Applies and returns the new calibration, or returns `None` if the wheel never stopped
+    /// or never turned the robot. Only wheels that turn the robot can be calibrated this way.
+    pub fn calibrate_neutral<I, E, CT>(
+        &mut self,
+        wheel: Wheel,
+        gyro: &mut Mpu6050<I, E>,
+        counter: &mut CounterUs<CT>,
+    ) -> Option<ServoCalibration>
+    where
+        I: WriteRead<Error = E> + Write<Error = E>,
+        E: core::fmt::Debug,
+        CT: Instance,
+    {
+        // Stop at once: the sweep never calls `update`, so a ramp would never finish
+        self.halt().ok()?;
+        match wheel {
+            Wheel::Front => find_neutral(&mut self.front_wheel, gyro, counter),
+            Wheel::Left => find_neutral(&mut self.left_wheel, gyro, counter),
+            Wheel::Right => find_neutral(&mut self.right_wheel, gyro, counter),
+        }
+    }
+}
+
+/// Sweeps the neutral offset of a stopped servo and measures how far the robot turns at each
+/// step. The neutral point is the middle of the offsets where the robot stays still.
+fn find_neutral<S, I, E, CT>(
+    servo: &mut S,
+    gyro: &mut Mpu6050<I, E>,
+    counter: &mut CounterUs<CT>,
+) -> Option<ServoCalibration>
+where
+    S: ServoOutput,
+    I: WriteRead<Error = E> + Write<Error = E>,
+    E: core::fmt::Debug,
+    CT: Instance,
+{
+    // Neutral offsets (us) to try
+    const SWEEP: i32 = 100;
+    const STEP: usize = 5;
+    // Time (us) for the wheel to settle at each offset, then to measure the turn
+    const SETTLE: u32 = 200_000;
+    const MEASURE: u32 = 500_000;
+    // Yaw change (rad) during the measurement below which the robot is still
+    const STILL: f32 = 0.5 * (consts::PI / 180.0);
+
+    let original = servo.calibration();
+    // First and last offset where the robot stayed still
+    let mut still: Option<(i32, i32)> = None;
+
+    for offset in (-SWEEP..=SWEEP).step_by(STEP) {
+        servo.set_calibration(ServoCalibration {
+            neutral_offset: offset,
+            ..original
+        });
+        read_gyro_for(gyro, counter, SETTLE);
+        let start = gyro.read().yaw.value();
+        let end = read_gyro_for(gyro, counter, MEASURE);
+
+        let mut turn = libm::fabsf(end - start);
+        if turn > consts::PI {
+            turn = 2.0 * consts::PI - turn;
+        }
+        defmt::debug!("Neutral offset: {} us, turn: {} rad", offset, turn);
+        if turn < STILL {
+            still = Some((still.map_or(offset, |(first, _)| first), offset));
+        }
+    }
+
+    match still {
+        Some((first, last)) if first != -SWEEP || last != SWEEP => {
+            let calibration = ServoCalibration {
+                neutral_offset: (first + last) / 2,
+                deadband: (last - first) / 2,
+                ..original
+            };
+            servo.set_calibration(calibration);
+            Some(calibration)
+        }
+        _ => {
+            servo.set_calibration(original);
+            None
+        }
+    }
+}
+
+/// Keep reading the gyro for `us` so its FIFO doesn't fill, then return the last yaw (rad)
+fn read_gyro_for<I, E, CT>(gyro: &mut Mpu6050<I, E>, counter: &mut CounterUs<CT>, us: u32) -> f32
+where
+    I: WriteRead<Error = E> + Write<Error = E>,
+    E: core::fmt::Debug,
+    CT: Instance,
+{
+    counter.start((2 * us).micros()).unwrap();
+    let mut yaw = gyro.read().yaw.value();
+    while counter.now().ticks() < us {
+        yaw = gyro.read().yaw.value();
+    }
+    counter.cancel().unwrap();
+    yaw
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Servo output that only records its state
+    #[derive(Default)]
+    struct MockServo {
+        position: i32,
+        target: i32,
+        slew_rate: Option<f32>,
+        detached: bool,
+        calibration: ServoCalibration,
+        input_range: InputRange,
+        pulse: (u32, u32),
+    }
+
+    impl ServoOutput for MockServo {
+        fn set_position(&mut self, position: i32) -> Result<(), servo::Error> {
+            self.position = position;
+            self.target = position;
+            Ok(())
+        }
+
+        fn set_target(&mut self, position: i32) -> Result<(), servo::Error> {
+            self.target = position;
+            if self.slew_rate.is_none() {
+                self.position = position;
+            }
+            Ok(())
+        }
+
+        fn set_slew_rate(&mut self, slew_rate: Option<f32>) {
+            self.slew_rate = slew_rate;
+        }
+
+        fn update(&mut self, _dt: u32) {
+            self.position = self.target;
+        }
+
+        fn detach(&mut self) {
+            self.detached = true;
+        }
+
+        fn attach(&mut self) {
+            self.detached = false;
+        }
+
+        fn is_attached(&self) -> bool {
+            !self.detached
+        }
+
+        fn brake(&mut self, _duration: u32) {
+            self.position = 0;
+            self.target = 0;
+        }
+
+        fn position(&self) -> i32 {
+            self.position
+        }
+
+        fn set_calibration(&mut self, calibration: ServoCalibration) {
+            self.calibration = calibration;
+        }
+
+        fn calibration(&self) -> ServoCalibration {
+            self.calibration
+        }
+
+        fn input_range(&self) -> InputRange {
+            self.input_range
+        }
+
+        fn set_input_range(&mut self, input_range: InputRange) -> Result<(), servo::Error> {
+            self.input_range = input_range;
+            Ok(())
+        }
+
+        fn pulse(&self) -> (u32, u32) {
+            self.pulse
+        }
+
+        fn set_pulse(&mut self, min_pulse: u32, max_pulse: u32) -> Result<(), servo::Error> {
+            self.pulse = (min_pulse, max_pulse);
+            Ok(())
+        }
+    }
+
+    /// Range sensor that finishes a measurement on every poll
+    struct MockRange(f32);
+
+    impl RangeSensor for MockRange {
+        fn read_mm(&mut self) -> Result<f32, RangeError> {
+            Ok(self.0)
+        }
+
+        fn start(&mut self) -> nb::Result<(), RangeError> {
+            Ok(())
+        }
+
+        fn poll_mm(&mut self) -> nb::Result<f32, RangeError> {
+            Ok(self.0)
+        }
+    }
+
+    fn mikoto(config: MikotoConfig) -> Mikoto<MockServo, MockServo, MockServo> {
+        Mikoto::builder(
+            MockServo::default(),
+            MockServo::default(),
+            MockServo::default(),
+        )
+        .config(config)
+        .build()
+        .unwrap()
+    }
+
+    fn radians(value: f32) -> Angle<angle_unit::Radians> {
+        Angle::new(value)
+    }
+
+    #[test]
+    fn safety_stop_overrides_a_published_turn() {
+        let mut mikoto = mikoto(MikotoConfig::default());
+        let mut arbiter = CommandArbiter::new();
+
+        let (command, status) = mikoto.turn_command(radians(0.0), radians(1.0), 10_000);
+        assert_eq!(status, TurnStatus::InProgress);
+        assert!(command.angular > 0.0);
+        // Only computing the command leaves the wheels alone
+        assert_eq!(mikoto.commanded_velocity(), (0.0, 0.0));
+
+        arbiter.publish(Priority::Turn, command, 50_000);
+        assert_eq!(
+            mikoto.drive_arbitrated(&mut arbiter, 10_000).unwrap(),
+            Some(Priority::Turn)
+        );
+        assert!(mikoto.commanded_velocity().1 > 0.0);
+
+        arbiter.publish(Priority::Safety, MotionCommand::STOP, 50_000);
+        let (command, _) = mikoto.turn_command(radians(0.1), radians(1.0), 10_000);
+        arbiter.publish(Priority::Turn, command, 50_000);
+        assert_eq!(
+            mikoto.drive_arbitrated(&mut arbiter, 10_000).unwrap(),
+            Some(Priority::Safety)
+        );
+        assert_eq!(mikoto.commanded_velocity(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn halt_ignores_the_acceleration_limit() {
+        let mut mikoto = mikoto(MikotoConfig {
+            acceleration_limit: Some(100.0),
+            ..MikotoConfig::default()
+        });
+        mikoto.drive(Direction::Forward, 80).unwrap();
+        mikoto.update(1_000_000);
+        assert_eq!(mikoto.left_wheel.position(), 80);
+
+        // Stopping only ramps down on later updates
+        mikoto.stop().unwrap();
+        assert_eq!(mikoto.left_wheel.position(), 80);
+
+        mikoto.halt().unwrap();
+        assert_eq!(mikoto.front_wheel.position(), 0);
+        assert_eq!(mikoto.left_wheel.position(), 0);
+        assert_eq!(mikoto.right_wheel.position(), 0);
+        assert_eq!(mikoto.commanded_velocity(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn autotune_relay_goes_through_the_kinematics() {
+        let mut mikoto = mikoto(MikotoConfig::default());
+        let mut autotune = RelayAutotune::new(1.0, 20, 0.05, 3, 60_000_000).unwrap();
+        let status = mikoto
+            .autotune_heading(&mut autotune, radians(0.0), 10_000)
+            .unwrap();
+        assert_eq!(status, AutotuneStatus::InProgress);
+        assert!(autotune.turning_left());
+        let (linear, angular) = mikoto.commanded_velocity();
+        assert!(libm::fabsf(linear) < 0.5);
+        assert!(libm::fabsf(angular - 20.0) < 0.5);
+
+        // Past the target, the relay turns right at the same angular velocity
+        mikoto
+            .autotune_heading(&mut autotune, radians(1.5), 10_000)
+            .unwrap();
+        assert!(!autotune.turning_left());
+        let (_, angular) = mikoto.commanded_velocity();
+        assert!(libm::fabsf(angular + 20.0) < 0.5);
+    }
+
+    #[test]
+    fn follow_wall_keeps_heading_integral_and_derivative() {
+        let mut mikoto = mikoto(MikotoConfig {
+            heading: HeadingConfig {
+                kp: 10.0,
+                ki: 100.0,
+                kd: 0.2,
+                derivative_filter: 1.0,
+                ..HeadingConfig::default()
+            },
+            ..MikotoConfig::default()
+        });
+        let mut wall = WallFollow::new(WallFollowConfig::default(), radians(0.0));
+
+        // Drifting away from a wall on the right, so the held heading changes every tick
+        let mut integral = 0.0;
+        for tick in 0..5 {
+            let mut sensor = MockRange(200.0 + 5.0 * tick as f32);
+            let yaw = radians(0.01 * tick as f32);
+            let (_, heading) = mikoto
+                .follow_wall(&mut wall, &mut sensor, yaw, 50, 10_000)
+                .unwrap();
+            // The integral builds up rather than restarting from one tick's error
+            assert!(heading.i < integral);
+            integral = heading.i;
+            if tick > 0 {
+                // Turning left is damped, which needs the previous yaw
+                assert!(heading.d < 0.0);
+            }
+        }
+    }
 }
@@ -8,17 +8,20 @@ use panic_probe as _;
 mod app {
     use lazy_static::lazy_static;
     use mikoto_bot::angle_unit::{Degrees, Radians};
+    use mikoto_bot::filter::Median;
     use mikoto_bot::pac::{I2C1, I2C2, TIM2, TIM4};
     use mikoto_bot::{
         hal::{
+            dwt::MonoTimer,
             gpio::{Alternate, Edge, OpenDrain, Pin},
             i2c,
             i2c::I2c,
             prelude::*,
             timer::{CounterUs, DelayUs, Instance},
         },
-        pac, Angle, Button, Direction, Led, Mikoto, MikotoPeripherals, MikotoWheels, Mpu6050,
-        Vl53l1x, YawPitchRoll,
+        pac, Angle, Button, Direction, Led, Mikoto, MikotoPeripherals, MikotoWheels, MoveStatus,
+        Mpu6050, Pose, RangeFilter, StallDetector, StallStatus, TrapezoidalProfile, Vl53l1x,
+        YawPitchRoll,
     };
     use mpu6050_dmp::yaw_pitch_roll::YawPitchRoll as YPR;
 
@@ -43,6 +46,7 @@ mod app {
         WaitForButton,
         ApproachWall,
         ClimbUp,
+        BackOff,
         ClimbOver,
         ClimbDown,
         FindPole,
@@ -60,6 +64,7 @@ mod app {
         mikoto: Mikoto,
         delay: DelayUs<TIM4>,
         counter: CounterUs<TIM2>,
+        mono: MonoTimer,
         i2c: I2c2,
         gyro: Mpu6050<I2c1, i2c::Error>,
         tof: Vl53l1x,
@@ -78,6 +83,7 @@ mod app {
 
         let mut delay = dp.TIM4.delay_us(&clocks);
         let mut counter = dp.TIM2.counter_us(&clocks);
+        let mono = MonoTimer::new(ctx.core.DWT, ctx.core.DCB, &clocks);
 
         let gpioa = dp.GPIOA.split();
         let gpiob = dp.GPIOB.split();
@@ -131,6 +137,7 @@ mod app {
                 mikoto,
                 delay,
                 counter,
+                mono,
                 gyro,
                 tof,
                 i2c: i2c2,
@@ -139,7 +146,7 @@ mod app {
         )
     }
 
-    #[idle(shared = [task], local = [mikoto, gyro, tof, i2c, counter, delay])]
+    #[idle(shared = [task], local = [mikoto, gyro, tof, i2c, counter, mono, delay])]
     fn idle(ctx: idle::Context) -> ! {
         let mut task = ctx.shared.task;
 
@@ -149,6 +156,8 @@ mod app {
         let i2c: &mut I2c2 = ctx.local.i2c;
         let counter: &mut CounterUs<TIM2> = ctx.local.counter;
         let delay: &mut DelayUs<TIM4> = ctx.local.delay;
+        let mono: MonoTimer = *ctx.local.mono;
+        let ticks_per_us = mono.frequency().raw() / 1_000_000;
 
         // Distance (in mm) from the wall in which we ignore any anomalies detected
         const BUFFER: f32 = 250.0;
@@ -167,6 +176,9 @@ mod app {
 
         let mut scan_pause = false;
 
+        // A single spurious ToF reading must not be mistaken for the pole
+        let mut scan_filter = Median::<3>::new();
+
         enum Scan {
             Stop,
             Left,
@@ -175,8 +187,28 @@ mod app {
 
         let mut scan = Scan::Stop;
 
+        // Distance (mm) from the pole at which to stop
+        const POLE_STOP_DIST: f32 = 150.0;
+        // Top speed (mm/s) and acceleration (mm/s²) approaching the pole
+        const APPROACH_SPEED: f32 = 200.0;
+        const APPROACH_ACCEL: f32 = 200.0;
+        // Slow down smoothly on the approach to the pole found while scanning
+        let mut approach: Option<TrapezoidalProfile> = None;
+
+        // Stuck on the lip of the wall while climbing
+        let mut stall = StallDetector::default();
+        // Speed and time (us) to ease back down the wall before trying again
+        const BACK_OFF_SPEED: u32 = 30;
+        const BACK_OFF_TIME: u32 = 500_000;
+
+        let mut last_loop = mono.now();
+
         // The idle loop
         loop {
+            // Time (us) since the last loop
+            let dt = last_loop.elapsed() / ticks_per_us;
+            last_loop = mono.now();
+
             let mut gyro_reading = gyro.read();
             // Gyro is mounted upside down, so directions are reversed.
             gyro_reading = YawPitchRoll::from(YPR {
@@ -191,26 +223,34 @@ mod app {
                 gyro_reading.roll.to_degrees()
             );
 
+            // Yaw increases to the right, odometry expects the opposite
+            let pose = mikoto.update_odometry(-gyro_reading.yaw, dt);
+            defmt::debug!("pose: {}", pose);
+
             task.lock(|t: &mut Task| match t {
                 Task::WaitForButton => {
-                    mikoto.stop().unwrap();
+                    mikoto.coast();
                 }
                 Task::ApproachWall => {
                     if gyro_reading.pitch.to_degrees() >= Angle::new(60.0) {
                         defmt::info!("Mounted wall...");
+                        stall.reset();
                         *t = Task::ClimbUp;
                     }
                     // Changing pitch affects yaw measurements,
                     // so only conduct yaw correction while on the floor.
                     if gyro_reading.pitch.to_degrees() <= Angle::new(5.0) {
-                        mikoto
+                        // Yaw increases to the right, the heading controller expects the opposite
+                        let heading = mikoto
                             .drive_straight(
-                                gyro_reading.yaw,
-                                offset_angle.to_radians(),
+                                -gyro_reading.yaw,
+                                -offset_angle.to_radians(),
                                 Direction::Forward,
                                 100,
+                                dt,
                             )
-                            .unwrap()
+                            .unwrap();
+                        defmt::debug!("heading: {}", heading);
                     } else {
                         mikoto.drive(Direction::Forward, 100).unwrap();
                     }
@@ -218,14 +258,36 @@ mod app {
                 Task::ClimbUp => {
                     mikoto.drive(Direction::Forward, 100).unwrap();
 
+                    // Rolling up the wall shakes the chassis, sitting on the lip doesn't
+                    let motion = gyro.read_motion();
+
                     #[allow(clippy::collapsible_if)]
-                    if gyro_reading.pitch.to_degrees() <= Angle::new(45.0) {
+                    if mikoto.detect_stall(&mut stall, gyro_reading, motion, None, dt)
+                        == StallStatus::Stalled
+                    {
+                        defmt::warn!("Stuck climbing wall, backing off...");
+                        if c_started {
+                            counter.cancel().unwrap();
+                            c_started = false;
+                        }
+                        *t = Task::BackOff;
+                    } else if gyro_reading.pitch.to_degrees() <= Angle::new(45.0) {
                         if wait_until(counter, &mut c_started, 500_000) {
                             defmt::info!("Reached peak of wall...");
                             *t = Task::ClimbOver;
                         }
                     }
                 }
+                Task::BackOff => {
+                    // Ease off the lip slowly, so the robot doesn't drop off the wall
+                    mikoto.drive(Direction::Backward, BACK_OFF_SPEED).unwrap();
+
+                    if wait_until(counter, &mut c_started, BACK_OFF_TIME) {
+                        defmt::info!("Retrying wall...");
+                        stall.reset();
+                        *t = Task::ClimbUp;
+                    }
+                }
                 Task::ClimbOver => {
                     if gyro_reading.pitch.to_degrees() <= Angle::new(-45.0) {
                         mikoto.drive(Direction::Forward, 15).unwrap();
@@ -248,6 +310,8 @@ mod app {
                     if gyro_reading.pitch.to_degrees() >= Angle::new(-10.0) {
                         if wait_until(counter, &mut c_started, 800_000) {
                             defmt::info!("Dismounted wall...");
+                            // The base of the wall is the origin for the rest of the course
+                            mikoto.reset_pose(Pose::default(), -gyro_reading.yaw);
                             *t = Task::FindPole;
                         }
                     }
@@ -266,37 +330,58 @@ mod app {
                                 *t = Task::ApproachPole;
                             }
                         } else {
+                            scan_filter.reset();
                             scan = Scan::Left;
                         }
                     }
                     Scan::Left => {
                         let angle = gyro_reading.yaw;
-                        let distance = tof.read(i2c, delay);
+                        let distance = scan_filter.update(tof.read(i2c, delay) as f32);
                         let expected = expected_dist(&angle);
                         mikoto.drive(Direction::Left, 5).unwrap();
 
                         if angle.to_degrees() <= -SCAN_ANGLE {
                             defmt::info!("Scanning right...");
+                            // Readings from the other sweep were taken at other bearings
+                            scan_filter.reset();
                             scan = Scan::Right;
-                        } else if distance as f32 <= expected - BUFFER {
+                        } else if distance <= expected - BUFFER {
                             defmt::info!("Pole detected!");
                             defmt::info!("Distance: {} mm", distance);
+                            approach = Some(
+                                TrapezoidalProfile::new(
+                                    (distance - POLE_STOP_DIST).max(0.0),
+                                    APPROACH_SPEED,
+                                    APPROACH_ACCEL,
+                                )
+                                .unwrap(),
+                            );
                             scan = Scan::Stop;
                             scan_pause = true;
                         }
                     }
                     Scan::Right => {
                         let angle = gyro_reading.yaw;
-                        let distance = tof.read(i2c, delay);
+                        let distance = scan_filter.update(tof.read(i2c, delay) as f32);
                         let expected = expected_dist(&angle);
                         mikoto.drive(Direction::Right, 5).unwrap();
 
                         if angle.to_degrees() >= SCAN_ANGLE {
                             defmt::info!("Scanning left...");
+                            // Readings from the other sweep were taken at other bearings
+                            scan_filter.reset();
                             scan = Scan::Left;
-                        } else if distance as f32 <= expected - BUFFER {
+                        } else if distance <= expected - BUFFER {
                             defmt::info!("Pole detected!");
                             defmt::info!("Distance: {} mm", distance);
+                            approach = Some(
+                                TrapezoidalProfile::new(
+                                    (distance - POLE_STOP_DIST).max(0.0),
+                                    APPROACH_SPEED,
+                                    APPROACH_ACCEL,
+                                )
+                                .unwrap(),
+                            );
                             scan = Scan::Stop;
                             scan_pause = true;
                         }
@@ -304,7 +389,7 @@ mod app {
                 },
                 Task::ApproachPole => {
                     let distance = tof.read(i2c, delay);
-                    let found_pole = distance < 150;
+                    let found_pole = (distance as f32) < POLE_STOP_DIST;
 
                     // front wheel, left/right wheel
                     on_pole_base = (
@@ -353,14 +438,31 @@ mod app {
                         }
                     } else {
                         // False positive roll or pitch condition: resume driving
-                        mikoto
-                            .drive_straight(
-                                gyro_reading.yaw,
-                                offset_angle.to_radians(),
-                                Direction::Forward,
-                                100,
-                            )
-                            .unwrap();
+                        let status = match approach.as_mut() {
+                            Some(profile) => mikoto
+                                .drive_profile(
+                                    profile,
+                                    -gyro_reading.yaw,
+                                    -offset_angle.to_radians(),
+                                    dt,
+                                )
+                                .unwrap(),
+                            None => MoveStatus::Done,
+                        };
+                        if status == MoveStatus::Done {
+                            // Pole is further than expected, creep toward it. Dropping the
+                            // finished profile keeps it from stopping the wheels every tick.
+                            approach = None;
+                            mikoto
+                                .drive_straight(
+                                    -gyro_reading.yaw,
+                                    -offset_angle.to_radians(),
+                                    Direction::Forward,
+                                    15,
+                                    dt,
+                                )
+                                .unwrap();
+                        }
                     }
                 }
             });
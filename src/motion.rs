@@ -0,0 +1,146 @@
+/// Reasons a `TrapezoidalProfile` can't be planned
+#[derive(Debug, Eq, PartialEq, Copy, Clone, defmt::Format)]
+pub enum ProfileError {
+    /// Top speed is not positive
+    InvalidSpeed,
+    /// Acceleration is not positive
+    InvalidAcceleration,
+}
+
+/// Trapezoidal motion profile for moving a set distance. Speeds up at a constant
+/// acceleration to a cruising speed, cruises, then slows down to stop exactly at the distance.
+/// Short moves never reach the cruising speed and have a triangular profile instead.
+#[derive(Debug, Copy, Clone)]
+pub struct TrapezoidalProfile {
+    /// Distance (mm), always positive
+    distance: f32,
+    /// 1 forward, -1 backward
+    direction: f32,
+    /// Acceleration (mm/s²)
+    acceleration: f32,
+    /// Highest speed reached (mm/s)
+    peak_speed: f32,
+    /// Time (s) spent speeding up, which is also the time spent slowing down
+    ramp_time: f32,
+    /// Time (s) spent at the peak speed
+    cruise_time: f32,
+    /// Time (us) since the start
+    elapsed: u32,
+}
+
+impl TrapezoidalProfile {
+    /// Move `distance` (mm, negative for backward) without exceeding `max_speed` (mm/s) or
+    /// `max_acceleration` (mm/s²), which must both be positive.
+    pub fn new(distance: f32, max_speed: f32, max_acceleration: f32) -> Result<Self, ProfileError> {
+        if max_speed.is_nan() || max_speed <= 0.0 {
+            return Err(ProfileError::InvalidSpeed);
+        }
+        if max_acceleration.is_nan() || max_acceleration <= 0.0 {
+            return Err(ProfileError::InvalidAcceleration);
+        }
+        let direction = if distance < 0.0 { -1.0 } else { 1.0 };
+        let distance = libm::fabsf(distance);
+
+        // Distance covered speeding up to max speed, then again slowing down
+        let ramp_distance = max_speed * max_speed / (2.0 * max_acceleration);
+        let (peak_speed, cruise_time) = if 2.0 * ramp_distance > distance {
+            (libm::sqrtf(distance * max_acceleration), 0.0)
+        } else {
+            (max_speed, (distance - 2.0 * ramp_distance) / max_speed)
+        };
+
+        Ok(Self {
+            distance,
+            direction,
+            acceleration: max_acceleration,
+            peak_speed,
+            ramp_time: peak_speed / max_acceleration,
+            cruise_time,
+            elapsed: 0,
+        })
+    }
+
+    /// Total time (s) of the move
+    pub fn duration(&self) -> f32 {
+        2.0 * self.ramp_time + self.cruise_time
+    }
+
+    /// Position (mm) and speed (mm/s) at `time` (s) since the start
+    pub fn sample(&self, time: f32) -> (f32, f32) {
+        let (position, speed) = if time <= 0.0 {
+            (0.0, 0.0)
+        } else if time < self.ramp_time {
+            // Speeding up
+            (
+                0.5 * self.acceleration * time * time,
+                self.acceleration * time,
+            )
+        } else if time < self.ramp_time + self.cruise_time {
+            let cruising = time - self.ramp_time;
+            (
+                0.5 * self.peak_speed * self.ramp_time + self.peak_speed * cruising,
+                self.peak_speed,
+            )
+        } else if time < self.duration() {
+            // Slowing down
+            let remaining = self.duration() - time;
+            (
+                self.distance - 0.5 * self.acceleration * remaining * remaining,
+                self.acceleration * remaining,
+            )
+        } else {
+            (self.distance, 0.0)
+        };
+        (self.direction * position, self.direction * speed)
+    }
+
+    /// Advance by `dt` (us) and return the speed (mm/s) to drive at, or `None` once finished.
+    pub fn update(&mut self, dt: u32) -> Option<f32> {
+        self.elapsed = self.elapsed.saturating_add(dt);
+        let time = self.elapsed as f32 * 1e-6;
+        if time >= self.duration() {
+            return None;
+        }
+        Some(self.sample(time).1)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed as f32 * 1e-6 >= self.duration()
+    }
+
+    /// Start the move again from the beginning
+    pub fn restart(&mut self) {
+        self.elapsed = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_non_positive_limits() {
+        assert_eq!(
+            TrapezoidalProfile::new(100.0, 0.0, 200.0).unwrap_err(),
+            ProfileError::InvalidSpeed
+        );
+        assert_eq!(
+            TrapezoidalProfile::new(100.0, 200.0, -1.0).unwrap_err(),
+            ProfileError::InvalidAcceleration
+        );
+        assert_eq!(
+            TrapezoidalProfile::new(100.0, f32::NAN, 200.0).unwrap_err(),
+            ProfileError::InvalidSpeed
+        );
+    }
+
+    #[test]
+    fn ends_at_distance() {
+        for distance in [50.0, 1000.0, -300.0] {
+            let profile = TrapezoidalProfile::new(distance, 200.0, 400.0).unwrap();
+            let (position, speed) = profile.sample(profile.duration());
+            assert!((position - distance).abs() < 1e-3);
+            assert_eq!(speed, 0.0);
+        }
+    }
+}
@@ -1,10 +1,13 @@
 use crate::angle_unit::*;
 use crate::hal::prelude::*;
+use crate::Temperature;
 use core::f32::consts;
 use core::{cmp::Ordering, fmt, marker::PhantomData, ops::Neg};
 use embedded_hal::blocking::delay::{DelayMs, DelayUs};
 use embedded_hal::blocking::i2c::{Write, WriteRead};
+use mpu6050_dmp::accel::AccelFullScale;
 use mpu6050_dmp::address::Address;
+use mpu6050_dmp::gyro::GyroFullScale;
 use mpu6050_dmp::quaternion::Quaternion;
 use mpu6050_dmp::sensor;
 use mpu6050_dmp::yaw_pitch_roll::YawPitchRoll as YPR;
@@ -16,7 +19,8 @@ where
     I: WriteRead<Error = E> + Write<Error = E>,
     E: core::fmt::Debug,
 {
-    device: sensor::Mpu6050<I>,
+    /// Only `None` while the bus is borrowed by `temperature`
+    device: Option<sensor::Mpu6050<I>>,
     offset: YawPitchRoll,
 }
 
@@ -31,7 +35,7 @@ where
         gyro.initialize_dmp(delay).unwrap();
 
         Self {
-            device: gyro,
+            device: Some(gyro),
             offset: YawPitchRoll::from(YPR {
                 yaw: 0.0,
                 pitch: 0.0,
@@ -56,10 +60,10 @@ where
 
     pub fn read(&mut self) -> YawPitchRoll {
         loop {
-            let len = self.device.get_fifo_count().unwrap();
+            let len = self.device().get_fifo_count().unwrap();
             if len >= 28 {
                 let mut buf = [0; 28];
-                let buf = self.device.read_fifo(&mut buf).unwrap();
+                let buf = self.device().read_fifo(&mut buf).unwrap();
                 let quat = Quaternion::from_bytes(&buf[..16]).unwrap();
                 let mut ypr = YPR::from(quat);
                 ypr.yaw *= 2.0; // Sets range from 0 to +-180,
@@ -72,6 +76,44 @@ where
         }
     }
 
+    /// Read the raw body rates and acceleration, bypassing the DMP. Uses the full scale
+    /// ranges set up by the DMP firmware.
+    pub fn read_motion(&mut self) -> ImuMotion {
+        let gyro = self.device().gyro().unwrap().scaled(GyroFullScale::Deg2000);
+        let accel = self.device().accel().unwrap().scaled(AccelFullScale::G2);
+
+        ImuMotion {
+            rate: (
+                gyro.x().to_radians(),
+                gyro.y().to_radians(),
+                gyro.z().to_radians(),
+            ),
+            acceleration: (accel.x(), accel.y(), accel.z()),
+        }
+    }
+
+    /// Read the die temperature. The die runs a few degrees above the air around it,
+    /// so this is a rough ambient temperature for speed of sound corrections.
+    pub fn temperature(&mut self) -> Temperature {
+        // First of the two temperature output registers
+        const TEMP_OUT_H: u8 = 0x41;
+
+        // The driver has no temperature read, so borrow its bus. Constructing the driver
+        // again only clears the sleep bit, leaving the DMP running.
+        let mut i2c = self.device.take().unwrap().release();
+        let mut buf = [0; 2];
+        let result = i2c.write_read(Address::default().into(), &[TEMP_OUT_H], &mut buf);
+        self.device = Some(sensor::Mpu6050::new(i2c, Address::default()).unwrap());
+        result.unwrap();
+
+        // Datasheet: T (°C) = raw / 340 + 36.53
+        Temperature::from_celsius(i16::from_be_bytes(buf) as f32 / 340.0 + 36.53)
+    }
+
+    fn device(&mut self) -> &mut sensor::Mpu6050<I> {
+        self.device.as_mut().unwrap()
+    }
+
     fn set_offset(value: &mut f32, offset: f32) {
         *value -= offset;
         if *value > consts::PI {
@@ -82,6 +124,12 @@ where
     }
 }
 
+/// Wraps an angle (rad) to -π..π
+pub(crate) fn wrap(angle: f32) -> f32 {
+    // The remainder is taken from the nearest multiple
+    libm::remainderf(angle, 2.0 * consts::PI)
+}
+
 pub mod unit {
     #[derive(Debug, Clone, Copy)]
     pub enum Radians {}
@@ -99,6 +147,30 @@ pub mod unit {
     }
 }
 
+/// Body rates and acceleration from a single sensor sample
+#[derive(Debug, Default, Copy, Clone, PartialEq, defmt::Format)]
+pub struct ImuMotion {
+    /// Angular rate (rad/s) about the x, y and z axes
+    pub rate: (f32, f32, f32),
+    /// Acceleration (g) along the x, y and z axes, gravity included
+    pub acceleration: (f32, f32, f32),
+}
+
+impl ImuMotion {
+    /// Fastest rotation (rad/s) about any axis
+    pub fn max_rate(&self) -> f32 {
+        let (x, y, z) = self.rate;
+        libm::fabsf(x).max(libm::fabsf(y)).max(libm::fabsf(z))
+    }
+
+    /// Acceleration (g) on top of gravity. The magnitude doesn't depend on how the robot is
+    /// tilted, so this picks up bumps and vibration on the floor and on the wall alike.
+    pub fn dynamic_acceleration(&self) -> f32 {
+        let (x, y, z) = self.acceleration;
+        libm::fabsf(libm::sqrtf(x * x + y * y + z * z) - 1.0)
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct YawPitchRoll {
     pub yaw: Angle<Radians>,
@@ -0,0 +1,76 @@
+use crate::angle_unit::Radians;
+use crate::mpu6050::wrap;
+use crate::Angle;
+
+/// Position and heading of the robot on the course
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct Pose {
+    /// Position (mm), x forward and y left of the reference heading
+    pub x: f32,
+    pub y: f32,
+    /// Heading, increasing to the left
+    pub theta: Angle<Radians>,
+}
+
+impl Pose {
+    pub const fn new(x: f32, y: f32, theta: Angle<Radians>) -> Self {
+        Self { x, y, theta }
+    }
+}
+
+impl Default for Pose {
+    fn default() -> Self {
+        Self::new(0.0, 0.0, Angle::new(0.0))
+    }
+}
+
+/// Dead reckoning of the robot's pose, from its ground speed and the IMU yaw.
+/// Error grows with distance, so reset at known landmarks.
+#[derive(Debug, Copy, Clone)]
+pub struct Odometry {
+    pose: Pose,
+    /// Added to the IMU yaw to get the pose heading (rad)
+    yaw_offset: f32,
+}
+
+impl Odometry {
+    pub fn new() -> Self {
+        Self {
+            pose: Pose::default(),
+            yaw_offset: 0.0,
+        }
+    }
+
+    /// Advance by `dt` (us) at ground `speed` (mm/s) with the IMU `yaw`, which must increase
+    /// when turning left.
+    pub fn update(&mut self, speed: f32, yaw: Angle<Radians>, dt: u32) -> Pose {
+        let theta = wrap(yaw.value() + self.yaw_offset);
+        // Average the heading over the step, going the short way around
+        let heading = self.pose.theta.value() + wrap(theta - self.pose.theta.value()) / 2.0;
+        let distance = speed * dt as f32 * 1e-6;
+        self.pose.x += distance * libm::cosf(heading);
+        self.pose.y += distance * libm::sinf(heading);
+        self.pose.theta = Angle::new(theta);
+        self.pose
+    }
+
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    /// Set the pose at a known landmark. `yaw` is the IMU yaw at the same moment, so later
+    /// headings are measured from the landmark's.
+    pub fn reset(&mut self, pose: Pose, yaw: Angle<Radians>) {
+        self.yaw_offset = wrap(pose.theta.value() - yaw.value());
+        self.pose = Pose {
+            theta: Angle::new(wrap(pose.theta.value())),
+            ..pose
+        };
+    }
+}
+
+impl Default for Odometry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -3,7 +3,7 @@ use crate::hal::{
     rcc::Clocks,
     time::Hertz,
     timer::{
-        pwm::Pins,
+        pwm::{Pins, PwmChannel},
         Channel,
         Channel::{C1, C2, C3, C4},
         PwmExt, PwmHz,
@@ -18,6 +18,12 @@ pub enum Error {
     PwmDisabled,
     /// Invalid servo position
     InvalidPosition,
+    /// Pulse range is empty, longer than the period or longer than 10000 us
+    InvalidPulse,
+    /// Input range is empty or spans more than 10000 positions
+    InvalidRange,
+    /// Period is shorter than a pulse
+    InvalidPeriod,
 }
 
 /// Servo Motor
@@ -28,9 +34,9 @@ where
 {
     pwm: PwmHz<TIM, P, PINS>,
     channel: Channel,
-    input_range: InputRange,
-    min_duty: f64,
-    max_duty: f64,
+    /// PWM period (us)
+    period: u32,
+    state: ServoState,
 }
 
 impl<TIM, PINS, P> Servo<TIM, PINS, P>
@@ -39,8 +45,8 @@ where
     TIM: PwmExt,
 {
     pub fn new(
-        min_pulse: f64,
-        max_pulse: f64,
+        min_pulse: u32,
+        max_pulse: u32,
         pin: PINS,
         timer: TIM,
         clocks: &Clocks,
@@ -49,13 +55,12 @@ where
         let channel = Self::open_channel()?;
 
         let mut servo = Self {
+            period: period_us(pwm.get_period()),
             pwm,
             channel,
-            input_range: InputRange::POSITIONAL_RANGE,
-            min_duty: 0f64,
-            max_duty: 0f64,
+            state: ServoState::new(),
         };
-        servo.set_pulse(min_pulse, max_pulse);
+        servo.set_pulse(min_pulse, max_pulse)?;
 
         Ok(servo)
     }
@@ -75,18 +80,849 @@ where
         }
     }
 
-    /// Returns the servo's zero position duty cycle
-    fn zero(&self) -> f64 {
-        self.min_duty + (self.max_duty - self.min_duty) / 2_f64
-    }
-
     /// Set the servo's position. Must give a position within the input range.
     pub fn set_position(&mut self, position: i32) -> Result<(), Error> {
-        if position == self.position() {
+        let (state, mut output) = self.parts();
+        state.set_position(&mut output, position)
+    }
+
+    /// Set the position the servo moves toward at the configured slew rate on each `update`.
+    /// Moves immediately if there is no slew rate.
+    pub fn set_target(&mut self, position: i32) -> Result<(), Error> {
+        let (state, mut output) = self.parts();
+        state.set_target(&mut output, position)
+    }
+
+    /// Get the position the servo is moving toward.
+    pub fn target(&self) -> i32 {
+        self.state.target
+    }
+
+    /// Limit how fast the position changes while moving toward a target, in position units
+    /// per second. For continuous servos this is an acceleration limit. `None` removes the limit.
+    pub fn set_slew_rate(&mut self, slew_rate: Option<f32>) {
+        self.state.set_slew_rate(slew_rate);
+    }
+
+    /// Move to `position` over `duration` (us), following `profile`. The position is
+    /// advanced by `update`, check `is_moving` for completion.
+    pub fn move_to(
+        &mut self,
+        position: i32,
+        duration: u32,
+        profile: MotionProfile,
+    ) -> Result<(), Error> {
+        let (state, mut output) = self.parts();
+        state.move_to(&mut output, position, duration, profile)
+    }
+
+    /// Whether a `move_to` is still in progress.
+    pub fn is_moving(&self) -> bool {
+        self.state.motion.is_some()
+    }
+
+    /// Step the position toward the target. `dt` is the time (us) since the last update.
+    /// Also ends a timed brake.
+    pub fn update(&mut self, dt: u32) {
+        let (state, mut output) = self.parts();
+        state.update(&mut output, dt);
+    }
+
+    /// Stop sending pulses. Positional servos go limp and continuous servos coast.
+    /// The position returns to the middle of the input range, so a continuous servo resumes
+    /// from stopped. Positions set while detached are applied on `attach`.
+    pub fn detach(&mut self) {
+        let (state, mut output) = self.parts();
+        state.detach(&mut output);
+    }
+
+    /// Resume sending pulses for the current position.
+    pub fn attach(&mut self) {
+        let (state, mut output) = self.parts();
+        state.attach(&mut output);
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.state.attached
+    }
+
+    /// Actively stop a continuous servo by holding zero for `duration` (us), then detach.
+    /// The time is counted by `update`.
+    pub fn brake(&mut self, duration: u32) {
+        let (state, mut output) = self.parts();
+        state.brake(&mut output, duration);
+    }
+
+    /// Get the servo's current position.
+    pub fn position(&self) -> i32 {
+        self.state.position
+    }
+
+    /// Set corrections for this servo's neutral point, deadband and gains.
+    pub fn set_calibration(&mut self, calibration: ServoCalibration) {
+        let (state, mut output) = self.parts();
+        state.set_calibration(&mut output, calibration);
+    }
+
+    pub fn calibration(&self) -> ServoCalibration {
+        self.state.calibration
+    }
+
+    pub fn input_range(&self) -> InputRange {
+        self.state.input_range
+    }
+
+    /// Get the pulse widths (us) mapped to the lower and upper limits of the input range.
+    pub fn pulse(&self) -> (u32, u32) {
+        (self.state.min_pulse, self.state.max_pulse)
+    }
+
+    /// Set a new range for servo position values, at most 10000 positions wide. Default = 0-180.
+    pub fn set_input_range(&mut self, input_range: InputRange) -> Result<(), Error> {
+        self.state.set_input_range(input_range)
+    }
+
+    /// Set a new pulse range (us) for the servo. Resets the servo to zero position.
+    pub fn set_pulse(&mut self, min_pulse: u32, max_pulse: u32) -> Result<(), Error> {
+        let (state, mut output) = self.parts();
+        state.set_pulse(&mut output, min_pulse, max_pulse)
+    }
+
+    /// Configure non-standard period (not 50Hz). Resets the servo to zero position.
+    pub fn set_period(&mut self, freq: Hertz) -> Result<(), Error> {
+        check_period(freq, self.state.max_pulse)?;
+        self.pwm.set_period(freq);
+        self.period = period_us(self.pwm.get_period());
+        let (state, mut output) = self.parts();
+        state.reset_position(&mut output);
+        Ok(())
+    }
+
+    fn parts(&mut self) -> (&mut ServoState, HzOutput<'_, TIM, PINS, P>) {
+        let output = HzOutput {
+            pwm: &mut self.pwm,
+            channel: self.channel,
+            period: self.period,
+        };
+        (&mut self.state, output)
+    }
+}
+
+/// A servo output Mikoto can drive a wheel with, so any timer and pin can be used.
+/// Implemented by `Servo` and by the `ChannelServo`s split off a `ServoBank`.
+pub trait ServoOutput {
+    /// Move to the position at once, ignoring the slew rate
+    fn set_position(&mut self, position: i32) -> Result<(), Error>;
+    /// Set the position to ramp toward at the slew rate
+    fn set_target(&mut self, position: i32) -> Result<(), Error>;
+    /// Limit how fast the position changes, in positions per second
+    fn set_slew_rate(&mut self, slew_rate: Option<f32>);
+    /// Step the ramp and brake by `dt` (us)
+    fn update(&mut self, dt: u32);
+    fn detach(&mut self);
+    fn attach(&mut self);
+    fn is_attached(&self) -> bool;
+    /// Hold zero for `duration` (us), then detach
+    fn brake(&mut self, duration: u32);
+    fn position(&self) -> i32;
+    fn set_calibration(&mut self, calibration: ServoCalibration);
+    fn calibration(&self) -> ServoCalibration;
+    fn input_range(&self) -> InputRange;
+    fn set_input_range(&mut self, input_range: InputRange) -> Result<(), Error>;
+    /// Pulse widths (us) mapped to the lower and upper limits of the input range
+    fn pulse(&self) -> (u32, u32);
+    fn set_pulse(&mut self, min_pulse: u32, max_pulse: u32) -> Result<(), Error>;
+}
+
+impl<TIM, PINS, P> ServoOutput for Servo<TIM, PINS, P>
+where
+    PINS: Pins<TIM, P>,
+    TIM: PwmExt,
+{
+    fn set_position(&mut self, position: i32) -> Result<(), Error> {
+        Servo::set_position(self, position)
+    }
+
+    fn set_target(&mut self, position: i32) -> Result<(), Error> {
+        Servo::set_target(self, position)
+    }
+
+    fn set_slew_rate(&mut self, slew_rate: Option<f32>) {
+        Servo::set_slew_rate(self, slew_rate)
+    }
+
+    fn update(&mut self, dt: u32) {
+        Servo::update(self, dt)
+    }
+
+    fn detach(&mut self) {
+        Servo::detach(self)
+    }
+
+    fn attach(&mut self) {
+        Servo::attach(self)
+    }
+
+    fn is_attached(&self) -> bool {
+        Servo::is_attached(self)
+    }
+
+    fn brake(&mut self, duration: u32) {
+        Servo::brake(self, duration)
+    }
+
+    fn position(&self) -> i32 {
+        Servo::position(self)
+    }
+
+    fn set_calibration(&mut self, calibration: ServoCalibration) {
+        Servo::set_calibration(self, calibration)
+    }
+
+    fn calibration(&self) -> ServoCalibration {
+        Servo::calibration(self)
+    }
+
+    fn input_range(&self) -> InputRange {
+        Servo::input_range(self)
+    }
+
+    fn set_input_range(&mut self, input_range: InputRange) -> Result<(), Error> {
+        Servo::set_input_range(self, input_range)
+    }
+
+    fn pulse(&self) -> (u32, u32) {
+        Servo::pulse(self)
+    }
+
+    fn set_pulse(&mut self, min_pulse: u32, max_pulse: u32) -> Result<(), Error> {
+        Servo::set_pulse(self, min_pulse, max_pulse)
+    }
+}
+
+/// Up to four servos on the channels of one timer. All servos share the timer's period,
+/// but each has its own input range, pulses and calibration.
+///
+/// Servos are addressed by their timer channel. Channels without a pin return
+/// `Error::PwmDisabled`.
+pub struct ServoBank<TIM, PINS, P>
+where
+    PINS: Pins<TIM, P>,
+    TIM: PwmExt,
+{
+    pwm: PwmHz<TIM, P, PINS>,
+    /// PWM period (us)
+    period: u32,
+    servos: [Option<ServoState>; 4],
+}
+
+impl<TIM, PINS, P> ServoBank<TIM, PINS, P>
+where
+    PINS: Pins<TIM, P>,
+    TIM: PwmExt,
+{
+    /// Every servo starts with the same pulse range, change it per channel with `set_pulse`.
+    pub fn new(
+        min_pulse: u32,
+        max_pulse: u32,
+        pins: PINS,
+        timer: TIM,
+        clocks: &Clocks,
+    ) -> Result<Self, Error> {
+        let pwm = timer.pwm_hz(pins, 50.Hz(), clocks);
+        let pin_channels = [PINS::C1, PINS::C2, PINS::C3, PINS::C4];
+        if !pin_channels.contains(&true) {
+            return Err(Error::PwmDisabled);
+        }
+
+        let mut bank = Self {
+            period: period_us(pwm.get_period()),
+            pwm,
+            servos: pin_channels.map(|enabled| enabled.then(ServoState::new)),
+        };
+        for channel in [C1, C2, C3, C4] {
+            if bank.state(channel).is_ok() {
+                bank.set_pulse(channel, min_pulse, max_pulse)?;
+            }
+        }
+
+        Ok(bank)
+    }
+
+    /// Set a servo's position. Must give a position within its input range.
+    pub fn set_position(&mut self, channel: Channel, position: i32) -> Result<(), Error> {
+        let (state, mut output) = self.parts(channel)?;
+        state.set_position(&mut output, position)
+    }
+
+    /// Set the position a servo moves toward at its slew rate on each `update`.
+    /// Moves immediately if there is no slew rate.
+    pub fn set_target(&mut self, channel: Channel, position: i32) -> Result<(), Error> {
+        let (state, mut output) = self.parts(channel)?;
+        state.set_target(&mut output, position)
+    }
+
+    pub fn target(&self, channel: Channel) -> Result<i32, Error> {
+        Ok(self.state(channel)?.target)
+    }
+
+    /// Limit how fast a servo's position changes while moving toward a target, in position
+    /// units per second. `None` removes the limit.
+    pub fn set_slew_rate(&mut self, channel: Channel, slew_rate: Option<f32>) -> Result<(), Error> {
+        self.state_mut(channel)?.set_slew_rate(slew_rate);
+        Ok(())
+    }
+
+    /// Move a servo to `position` over `duration` (us), following `profile`. The position is
+    /// advanced by `update`, check `is_moving` for completion.
+    pub fn move_to(
+        &mut self,
+        channel: Channel,
+        position: i32,
+        duration: u32,
+        profile: MotionProfile,
+    ) -> Result<(), Error> {
+        let (state, mut output) = self.parts(channel)?;
+        state.move_to(&mut output, position, duration, profile)
+    }
+
+    /// Whether a servo's `move_to` is still in progress.
+    pub fn is_moving(&self, channel: Channel) -> Result<bool, Error> {
+        Ok(self.state(channel)?.motion.is_some())
+    }
+
+    /// Step every servo toward its target. `dt` is the time (us) since the last update.
+    /// Also ends timed brakes.
+    pub fn update(&mut self, dt: u32) {
+        for channel in [C1, C2, C3, C4] {
+            if let Ok((state, mut output)) = self.parts(channel) {
+                state.update(&mut output, dt);
+            }
+        }
+    }
+
+    /// Stop sending pulses to a servo. Positional servos go limp and continuous servos coast.
+    /// The position returns to the middle of the input range, so a continuous servo resumes
+    /// from stopped. Positions set while detached are applied on `attach`.
+    pub fn detach(&mut self, channel: Channel) -> Result<(), Error> {
+        let (state, mut output) = self.parts(channel)?;
+        state.detach(&mut output);
+        Ok(())
+    }
+
+    /// Resume sending pulses for a servo's current position.
+    pub fn attach(&mut self, channel: Channel) -> Result<(), Error> {
+        let (state, mut output) = self.parts(channel)?;
+        state.attach(&mut output);
+        Ok(())
+    }
+
+    pub fn is_attached(&self, channel: Channel) -> Result<bool, Error> {
+        Ok(self.state(channel)?.attached)
+    }
+
+    /// Actively stop a continuous servo by holding zero for `duration` (us), then detach.
+    /// The time is counted by `update`.
+    pub fn brake(&mut self, channel: Channel, duration: u32) -> Result<(), Error> {
+        let (state, mut output) = self.parts(channel)?;
+        state.brake(&mut output, duration);
+        Ok(())
+    }
+
+    pub fn position(&self, channel: Channel) -> Result<i32, Error> {
+        Ok(self.state(channel)?.position)
+    }
+
+    /// Set corrections for a servo's neutral point, deadband and gains.
+    pub fn set_calibration(
+        &mut self,
+        channel: Channel,
+        calibration: ServoCalibration,
+    ) -> Result<(), Error> {
+        let (state, mut output) = self.parts(channel)?;
+        state.set_calibration(&mut output, calibration);
+        Ok(())
+    }
+
+    pub fn calibration(&self, channel: Channel) -> Result<ServoCalibration, Error> {
+        Ok(self.state(channel)?.calibration)
+    }
+
+    pub fn input_range(&self, channel: Channel) -> Result<InputRange, Error> {
+        Ok(self.state(channel)?.input_range)
+    }
+
+    /// Set a new range for a servo's position values. Default = 0-180.
+    pub fn set_input_range(
+        &mut self,
+        channel: Channel,
+        input_range: InputRange,
+    ) -> Result<(), Error> {
+        self.state_mut(channel)?.set_input_range(input_range)
+    }
+
+    /// Get the pulse widths (us) mapped to the limits of a servo's input range.
+    pub fn pulse(&self, channel: Channel) -> Result<(u32, u32), Error> {
+        let state = self.state(channel)?;
+        Ok((state.min_pulse, state.max_pulse))
+    }
+
+    /// Set a new pulse range (us) for a servo. Resets the servo to zero position.
+    pub fn set_pulse(
+        &mut self,
+        channel: Channel,
+        min_pulse: u32,
+        max_pulse: u32,
+    ) -> Result<(), Error> {
+        let (state, mut output) = self.parts(channel)?;
+        state.set_pulse(&mut output, min_pulse, max_pulse)
+    }
+
+    /// Configure non-standard period (not 50Hz). Resets every servo to zero position.
+    pub fn set_period(&mut self, freq: Hertz) -> Result<(), Error> {
+        for state in self.servos.iter().flatten() {
+            check_period(freq, state.max_pulse)?;
+        }
+        self.pwm.set_period(freq);
+        self.period = period_us(self.pwm.get_period());
+        for channel in [C1, C2, C3, C4] {
+            if let Ok((state, mut output)) = self.parts(channel) {
+                state.reset_position(&mut output);
+            }
+        }
+        Ok(())
+    }
+
+    /// Split the bank into one `ChannelServo` per channel with a pin, e.g. a
+    /// `(ChannelServo<TIM, 0>, ChannelServo<TIM, 1>, ChannelServo<TIM, 2>)` for three wheels on
+    /// one timer. Each servo keeps its position and configuration, but the period can no
+    /// longer be changed.
+    pub fn split(self) -> <PINS::Channels as IntoChannelServos>::Servos
+    where
+        PINS::Channels: IntoChannelServos,
+    {
+        let states = ServoStates {
+            period: self.period,
+            servos: self.servos,
+        };
+        self.pwm.split().into_servos(states)
+    }
+
+    fn state(&self, channel: Channel) -> Result<&ServoState, Error> {
+        self.servos[channel as usize]
+            .as_ref()
+            .ok_or(Error::PwmDisabled)
+    }
+
+    fn state_mut(&mut self, channel: Channel) -> Result<&mut ServoState, Error> {
+        self.servos[channel as usize]
+            .as_mut()
+            .ok_or(Error::PwmDisabled)
+    }
+
+    fn parts(
+        &mut self,
+        channel: Channel,
+    ) -> Result<(&mut ServoState, HzOutput<'_, TIM, PINS, P>), Error> {
+        let state = self.servos[channel as usize]
+            .as_mut()
+            .ok_or(Error::PwmDisabled)?;
+        let output = HzOutput {
+            pwm: &mut self.pwm,
+            channel,
+            period: self.period,
+        };
+        Ok((state, output))
+    }
+}
+
+/// One servo of a `ServoBank`, owned separately from the bank's other servos
+pub struct ChannelServo<TIM, const C: u8> {
+    channel: PwmChannel<TIM, C>,
+    /// PWM period (us)
+    period: u32,
+    state: ServoState,
+}
+
+impl<TIM: PwmExt, const C: u8> ChannelServo<TIM, C> {
+    fn parts(&mut self) -> (&mut ServoState, ChannelOutput<'_, TIM, C>) {
+        let output = ChannelOutput {
+            channel: &mut self.channel,
+            period: self.period,
+        };
+        (&mut self.state, output)
+    }
+}
+
+impl<TIM: PwmExt, const C: u8> ServoOutput for ChannelServo<TIM, C> {
+    fn set_position(&mut self, position: i32) -> Result<(), Error> {
+        let (state, mut output) = self.parts();
+        state.set_position(&mut output, position)
+    }
+
+    fn set_target(&mut self, position: i32) -> Result<(), Error> {
+        let (state, mut output) = self.parts();
+        state.set_target(&mut output, position)
+    }
+
+    fn set_slew_rate(&mut self, slew_rate: Option<f32>) {
+        self.state.set_slew_rate(slew_rate);
+    }
+
+    fn update(&mut self, dt: u32) {
+        let (state, mut output) = self.parts();
+        state.update(&mut output, dt);
+    }
+
+    fn detach(&mut self) {
+        let (state, mut output) = self.parts();
+        state.detach(&mut output);
+    }
+
+    fn attach(&mut self) {
+        let (state, mut output) = self.parts();
+        state.attach(&mut output);
+    }
+
+    fn is_attached(&self) -> bool {
+        self.state.attached
+    }
+
+    fn brake(&mut self, duration: u32) {
+        let (state, mut output) = self.parts();
+        state.brake(&mut output, duration);
+    }
+
+    fn position(&self) -> i32 {
+        self.state.position
+    }
+
+    fn set_calibration(&mut self, calibration: ServoCalibration) {
+        let (state, mut output) = self.parts();
+        state.set_calibration(&mut output, calibration);
+    }
+
+    fn calibration(&self) -> ServoCalibration {
+        self.state.calibration
+    }
+
+    fn input_range(&self) -> InputRange {
+        self.state.input_range
+    }
+
+    fn set_input_range(&mut self, input_range: InputRange) -> Result<(), Error> {
+        self.state.set_input_range(input_range)
+    }
+
+    fn pulse(&self) -> (u32, u32) {
+        (self.state.min_pulse, self.state.max_pulse)
+    }
+
+    fn set_pulse(&mut self, min_pulse: u32, max_pulse: u32) -> Result<(), Error> {
+        let (state, mut output) = self.parts();
+        state.set_pulse(&mut output, min_pulse, max_pulse)
+    }
+}
+
+/// Servos of a `ServoBank` being split into `ChannelServo`s
+pub struct ServoStates {
+    /// PWM period (us)
+    period: u32,
+    servos: [Option<ServoState>; 4],
+}
+
+impl ServoStates {
+    fn take<TIM, const C: u8>(&mut self, channel: PwmChannel<TIM, C>) -> ChannelServo<TIM, C> {
+        ChannelServo {
+            channel,
+            period: self.period,
+            // The bank holds a servo for every channel with a pin
+            state: self.servos[C as usize].take().unwrap(),
+        }
+    }
+}
+
+/// PWM channels of a timer that a `ServoBank` can be split into
+pub trait IntoChannelServos {
+    type Servos;
+
+    fn into_servos(self, states: ServoStates) -> Self::Servos;
+}
+
+impl<TIM: PwmExt, const C: u8> IntoChannelServos for PwmChannel<TIM, C> {
+    type Servos = ChannelServo<TIM, C>;
+
+    fn into_servos(self, mut states: ServoStates) -> Self::Servos {
+        states.take(self)
+    }
+}
+
+macro_rules! into_channel_servos {
+    ($(($C:ident, $channel:ident)),+) => {
+        impl<TIM: PwmExt, $(const $C: u8),+> IntoChannelServos for ($(PwmChannel<TIM, $C>),+) {
+            type Servos = ($(ChannelServo<TIM, $C>),+);
+
+            fn into_servos(self, mut states: ServoStates) -> Self::Servos {
+                let ($($channel),+) = self;
+                ($(states.take($channel)),+)
+            }
+        }
+    };
+}
+
+into_channel_servos!((A, a), (B, b));
+into_channel_servos!((A, a), (B, b), (C, c));
+into_channel_servos!((A, a), (B, b), (C, c), (D, d));
+
+/// A PWM channel a servo's pulses are sent on
+trait PwmOutput {
+    /// Send pulses `pulse` (us) wide
+    fn write_pulse(&mut self, pulse: u32);
+    fn set_enabled(&mut self, enabled: bool);
+    /// PWM period (us)
+    fn period(&self) -> u32;
+}
+
+/// One channel of a timer's PWM
+struct HzOutput<'a, TIM, PINS, P>
+where
+    PINS: Pins<TIM, P>,
+    TIM: PwmExt,
+{
+    pwm: &'a mut PwmHz<TIM, P, PINS>,
+    channel: Channel,
+    /// PWM period (us)
+    period: u32,
+}
+
+impl<TIM, PINS, P> PwmOutput for HzOutput<'_, TIM, PINS, P>
+where
+    PINS: Pins<TIM, P>,
+    TIM: PwmExt,
+{
+    fn write_pulse(&mut self, pulse: u32) {
+        let duty = pulse_as_duty(pulse, self.period, self.pwm.get_max_duty());
+        self.pwm.set_duty(self.channel, duty);
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        if enabled {
+            self.pwm.enable(self.channel);
+        } else {
+            self.pwm.disable(self.channel);
+        }
+    }
+
+    fn period(&self) -> u32 {
+        self.period
+    }
+}
+
+/// A PWM channel split off a timer
+struct ChannelOutput<'a, TIM, const C: u8> {
+    channel: &'a mut PwmChannel<TIM, C>,
+    /// PWM period (us)
+    period: u32,
+}
+
+impl<TIM: PwmExt, const C: u8> PwmOutput for ChannelOutput<'_, TIM, C> {
+    fn write_pulse(&mut self, pulse: u32) {
+        let duty = pulse_as_duty(pulse, self.period, self.channel.get_max_duty());
+        self.channel.set_duty(duty);
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        if enabled {
+            self.channel.enable();
+        } else {
+            self.channel.disable();
+        }
+    }
+
+    fn period(&self) -> u32 {
+        self.period
+    }
+}
+
+/// Position, target and configuration of one servo output, independent of its timer.
+/// Positions are converted to pulses with integer math, so converting back is exact.
+#[derive(Debug, Copy, Clone)]
+struct ServoState {
+    input_range: InputRange,
+    /// Pulse widths (us) at the lower and upper limits of the input range
+    min_pulse: u32,
+    max_pulse: u32,
+    calibration: ServoCalibration,
+    position: i32,
+    slew_rate: Option<f32>,
+    target: i32,
+    ramp: f32,
+    /// Whether pulses are being sent
+    attached: bool,
+    /// Time (us) left holding zero before detaching
+    brake: Option<u32>,
+    motion: Option<Motion>,
+}
+
+/// Operations on a servo that also drive its PWM output, shared by every kind of servo
+impl ServoState {
+    fn set_position(&mut self, output: &mut impl PwmOutput, position: i32) -> Result<(), Error> {
+        self.check_position(position)?;
+        self.target = position;
+        self.ramp = position as f32;
+        self.brake = None;
+        self.motion = None;
+        self.apply_position(output, position);
+        Ok(())
+    }
+
+    fn set_target(&mut self, output: &mut impl PwmOutput, position: i32) -> Result<(), Error> {
+        if self.slew_rate.is_none() {
+            return self.set_position(output, position);
+        }
+        self.check_position(position)?;
+        self.target = position;
+        self.brake = None;
+        self.motion = None;
+        Ok(())
+    }
+
+    fn set_slew_rate(&mut self, slew_rate: Option<f32>) {
+        self.slew_rate = slew_rate;
+        self.ramp = self.position as f32;
+    }
+
+    fn move_to(
+        &mut self,
+        output: &mut impl PwmOutput,
+        position: i32,
+        duration: u32,
+        profile: MotionProfile,
+    ) -> Result<(), Error> {
+        if duration == 0 {
+            return self.set_position(output, position);
+        }
+        self.start_motion(position, duration, profile)
+    }
+
+    fn update(&mut self, output: &mut impl PwmOutput, dt: u32) {
+        if let Some(position) = self.step(dt) {
+            self.apply_position(output, position);
+        }
+        if self.brake_elapsed(dt) {
+            self.detach(output);
+        }
+    }
+
+    fn detach(&mut self, output: &mut impl PwmOutput) {
+        output.set_enabled(false);
+        self.attached = false;
+        self.brake = None;
+        self.motion = None;
+        // The wheel stops while coasting, so don't resume at the old speed or ramp from it
+        let zero = self.reset();
+        self.write_position(output, zero);
+    }
+
+    fn attach(&mut self, output: &mut impl PwmOutput) {
+        output.set_enabled(true);
+        self.attached = true;
+    }
+
+    fn brake(&mut self, output: &mut impl PwmOutput, duration: u32) {
+        let zero = self.start_brake(duration);
+        self.write_position(output, zero);
+        self.attach(output);
+    }
+
+    fn set_calibration(&mut self, output: &mut impl PwmOutput, calibration: ServoCalibration) {
+        self.calibration = calibration;
+        self.write_position(output, self.position);
+    }
+
+    fn set_pulse(
+        &mut self,
+        output: &mut impl PwmOutput,
+        min_pulse: u32,
+        max_pulse: u32,
+    ) -> Result<(), Error> {
+        let zero = self.set_pulse_range(min_pulse, max_pulse, output.period())?;
+        self.write_position(output, zero);
+        Ok(())
+    }
+
+    /// Moves to zero, e.g. after the period changed
+    fn reset_position(&mut self, output: &mut impl PwmOutput) {
+        let zero = self.reset();
+        self.write_position(output, zero);
+    }
+
+    fn apply_position(&mut self, output: &mut impl PwmOutput, position: i32) {
+        if position == self.position {
             // No change
-            return Ok(());
+            return;
+        }
+        self.write_position(output, position);
+    }
+
+    fn write_position(&mut self, output: &mut impl PwmOutput, position: i32) {
+        output.write_pulse(self.position_as_pulse(position));
+        if self.attached {
+            output.set_enabled(true);
+        }
+        self.position = position;
+    }
+}
+
+impl ServoState {
+    fn new() -> Self {
+        Self {
+            input_range: InputRange::POSITIONAL_RANGE,
+            min_pulse: 0,
+            max_pulse: 0,
+            calibration: ServoCalibration::default(),
+            position: 0,
+            slew_rate: None,
+            target: 0,
+            ramp: 0.0,
+            attached: true,
+            brake: None,
+            motion: None,
+        }
+    }
+
+    /// Moves the target to the middle of the input range and returns it
+    fn reset(&mut self) -> i32 {
+        let (start, end) = self.input_range;
+        let zero = start + (end - start) / 2;
+        self.target = zero;
+        self.ramp = zero as f32;
+        zero
+    }
+
+    /// Moves the target to zero and starts counting down the brake time. Returns zero.
+    fn start_brake(&mut self, duration: u32) -> i32 {
+        self.brake = Some(duration);
+        self.motion = None;
+        self.reset()
+    }
+
+    /// Counts down the brake time, returning true once when it runs out
+    fn brake_elapsed(&mut self, dt: u32) -> bool {
+        match self.brake {
+            Some(remaining) if remaining > dt => {
+                self.brake = Some(remaining - dt);
+                false
+            }
+            Some(_) => {
+                self.brake = None;
+                true
+            }
+            None => false,
         }
+    }
 
+    fn check_position(&self, position: i32) -> Result<(), Error> {
         let (low, high);
         if self.input_range.0 < self.input_range.1 {
             low = self.input_range.0;
@@ -98,66 +934,251 @@ where
         if !(low..=high).contains(&position) {
             return Err(Error::InvalidPosition);
         }
+        Ok(())
+    }
 
-        let duty_limit = self.pwm.get_max_duty() as f64;
-        self.pwm.set_duty(
-            self.channel,
-            libm::round(duty_limit * self.position_as_duty(position)) as u16,
-        );
-        self.pwm.enable(self.channel);
+    /// Starts a timed move, advanced by `step`
+    fn start_motion(
+        &mut self,
+        position: i32,
+        duration: u32,
+        profile: MotionProfile,
+    ) -> Result<(), Error> {
+        self.check_position(position)?;
+        self.target = position;
+        self.brake = None;
+        self.motion = Some(Motion {
+            start: self.position,
+            end: position,
+            duration,
+            elapsed: 0,
+            profile,
+        });
         Ok(())
     }
 
-    /// Get the servo's current position.
-    pub fn position(&self) -> i32 {
-        let duty_ratio = self.pwm.get_duty(self.channel) as f64 / self.pwm.get_max_duty() as f64;
-        self.duty_as_position(duty_ratio)
+    /// Returns the next position of a timed move, or toward the target if the slew rate is
+    /// limited
+    fn step(&mut self, dt: u32) -> Option<i32> {
+        if let Some(motion) = self.motion.as_mut() {
+            let position = motion.step(dt);
+            if motion.elapsed >= motion.duration {
+                self.motion = None;
+            }
+            self.ramp = position as f32;
+            return Some(position);
+        }
+        let slew_rate = self.slew_rate?;
+        let max_step = slew_rate * dt as f32 * 1e-6;
+        let error = self.target as f32 - self.ramp;
+        self.ramp += error.clamp(-max_step, max_step);
+        Some(libm::roundf(self.ramp) as i32)
     }
 
-    fn duty_as_position(&self, duty: f64) -> i32 {
-        let input_start = self.input_range.0 as f64;
-        let input_end = self.input_range.1 as f64;
-        let position = (input_start * (duty - self.max_duty) + input_end * (self.min_duty - duty))
-            / (self.min_duty - self.max_duty);
-        libm::round(position) as i32
+    fn set_input_range(&mut self, input_range: InputRange) -> Result<(), Error> {
+        check_range(input_range)?;
+        // Keep the same pulse, but express it in the new range
+        let pulse = self.position_as_pulse(self.position) as i32 - self.calibration.neutral_offset;
+        self.input_range = input_range;
+        self.position = self.pulse_as_position(pulse);
+        self.target = self.position;
+        self.ramp = self.target as f32;
+        self.motion = None;
+        Ok(())
     }
 
-    /// Converts a position to its corresponding duty cycle using the configured input range
-    fn position_as_duty(&self, position: i32) -> f64 {
-        let input_start = self.input_range.0 as f64;
-        let input_end = self.input_range.1 as f64;
+    /// Sets the pulse range (us) and moves the target to zero. Returns zero.
+    fn set_pulse_range(
+        &mut self,
+        min_pulse: u32,
+        max_pulse: u32,
+        period: u32,
+    ) -> Result<i32, Error> {
+        if min_pulse >= max_pulse || max_pulse > period || max_pulse - min_pulse > MAX_PULSE_RANGE {
+            return Err(Error::InvalidPulse);
+        }
+        self.min_pulse = min_pulse;
+        self.max_pulse = max_pulse;
+        self.motion = None;
+        Ok(self.reset())
+    }
 
-        (position as f64 - input_start) / (input_end - input_start)
-            * (self.max_duty - self.min_duty)
-            + self.min_duty
+    /// Converts a position to its corresponding pulse width (us) using the configured input
+    /// range and calibration
+    fn position_as_pulse(&self, position: i32) -> u32 {
+        let (input_start, input_end) = self.input_range;
+        let span = input_end - input_start;
+        let pulse_range = (self.max_pulse - self.min_pulse) as i32;
+        // Offset from the center of the input range in half positions, so the center of an
+        // odd range is exact. Within the range it is at most `MAX_SPAN`, so the product below
+        // fits in 32 bits.
+        let offset = 2 * (position - input_start) - span;
+        let pulse_offset = if span == 0 {
+            0
+        } else {
+            div_round(offset * pulse_range * PULSE_SCALE, 2 * span)
+        };
+        let center = (self.min_pulse + self.max_pulse) as i32 * PULSE_SCALE / 2;
+        let pulse = div_round(
+            center + self.calibrated(pulse_offset, offset > 0),
+            PULSE_SCALE,
+        );
+        (pulse + self.calibration.neutral_offset).max(0) as u32
     }
 
-    /// Set a new range for servo position values. Default = 0-180.
-    pub fn set_input_range(&mut self, input_range: InputRange) {
-        self.input_range = input_range
+    /// Converts an uncalibrated pulse width (us) to the nearest position
+    fn pulse_as_position(&self, pulse: i32) -> i32 {
+        let (input_start, input_end) = self.input_range;
+        let (min_pulse, max_pulse) = (self.min_pulse as i32, self.max_pulse as i32);
+        if max_pulse <= min_pulse {
+            return input_start;
+        }
+        let pulse = pulse.clamp(min_pulse, max_pulse);
+        input_start
+            + div_round(
+                (pulse - min_pulse) * (input_end - input_start),
+                max_pulse - min_pulse,
+            )
     }
 
-    /// Set a new pulse range for the servo. Resets the servo to zero position.
-    pub fn set_pulse(&mut self, min_pulse: f64, max_pulse: f64) {
-        // Duty Cycle = pulse_width / period
-        // period = 1 / frequency
-        let period = 1_f64 / (self.pwm.get_period().raw() as f64 * 1e-6);
-        self.min_duty = min_pulse / period;
-        self.max_duty = max_pulse / period;
-        let duty_limit = self.pwm.get_max_duty() as f64;
-        self.pwm
-            .set_duty(self.channel, libm::round(duty_limit * self.zero()) as u16);
-        self.pwm.enable(self.channel);
+    /// Applies the calibration's deadband and gain corrections to a pulse offset from the
+    /// center of the pulse range, in `PULSE_SCALE` units. `forward` is whether the position is
+    /// above the center of the input range.
+    fn calibrated(&self, offset: i32, forward: bool) -> i32 {
+        let half_range = (self.max_pulse - self.min_pulse) as i32 * PULSE_SCALE / 2;
+        if offset == 0 || half_range == 0 {
+            return 0;
+        }
+
+        // Skip over the deadband, so the smallest nonzero positions still move the servo
+        let deadband = self.calibration.deadband.clamp(0, half_range / PULSE_SCALE) * PULSE_SCALE;
+        let gain = if forward {
+            self.calibration.forward_gain
+        } else {
+            self.calibration.reverse_gain
+        };
+        let magnitude = deadband as f32
+            + offset.abs() as f32 * (half_range - deadband) as f32 / half_range as f32;
+        let magnitude = (libm::roundf(magnitude * gain) as i32).min(half_range);
+        magnitude * offset.signum()
     }
+}
 
-    /// Configure non-standard period (not 50Hz). Resets the servo to zero position.
-    pub fn set_period(&mut self, freq: Hertz) {
-        let period = 1_f64 / (self.pwm.get_period().raw() as f64 * 1e-6);
-        let min_pulse = self.min_duty * period;
-        let max_pulse = self.max_duty * period;
-        self.pwm.set_period(freq);
-        // Set new min and max duty cycle ratios
-        self.set_pulse(min_pulse, max_pulse);
+/// Shape of a timed move
+#[derive(Debug, Copy, Clone, Eq, PartialEq, defmt::Format)]
+pub enum MotionProfile {
+    /// Constant speed
+    Linear,
+    /// Accelerate from rest and slow to a stop
+    EaseInOut,
+}
+
+impl MotionProfile {
+    /// Maps the fraction of the move's time elapsed to the fraction of its distance covered
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Self::Linear => t,
+            // Smoothstep
+            Self::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// A timed move in progress
+#[derive(Debug, Copy, Clone)]
+struct Motion {
+    start: i32,
+    end: i32,
+    /// Total and elapsed time (us)
+    duration: u32,
+    elapsed: u32,
+    profile: MotionProfile,
+}
+
+impl Motion {
+    /// Advances the move by `dt` (us) and returns the new position
+    fn step(&mut self, dt: u32) -> i32 {
+        self.elapsed = self.elapsed.saturating_add(dt).min(self.duration);
+        let t = self.elapsed as f32 / self.duration as f32;
+        let distance = (self.end - self.start) as f32 * self.profile.apply(t);
+        self.start + libm::roundf(distance) as i32
+    }
+}
+
+/// Fractions of a microsecond pulses are calculated in, so they are only rounded once
+const PULSE_SCALE: i32 = 16;
+/// Widest input range, in positions. Keeps position to pulse conversions within 32 bits.
+const MAX_SPAN: u32 = 10_000;
+/// Longest pulse range (us). Keeps position to pulse conversions within 32 bits.
+const MAX_PULSE_RANGE: u32 = 10_000;
+
+/// Integer division rounded to the nearest integer, halves away from zero
+fn div_round(numerator: i32, denominator: i32) -> i32 {
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    if 2 * remainder.abs() >= denominator.abs() {
+        quotient + numerator.signum() * denominator.signum()
+    } else {
+        quotient
+    }
+}
+
+/// Checks an input range is not empty and no wider than `MAX_SPAN`
+fn check_range(input_range: InputRange) -> Result<(), Error> {
+    let span = input_range.0.abs_diff(input_range.1);
+    if span == 0 || span > MAX_SPAN {
+        return Err(Error::InvalidRange);
+    }
+    Ok(())
+}
+
+/// Checks a PWM frequency's period is long enough for the longest pulse
+fn check_period(freq: Hertz, max_pulse: u32) -> Result<(), Error> {
+    if freq.raw() == 0 || period_us(freq) < max_pulse {
+        return Err(Error::InvalidPeriod);
+    }
+    Ok(())
+}
+
+/// Returns the period (us) of a PWM frequency
+fn period_us(freq: Hertz) -> u32 {
+    // period = 1 / frequency
+    1_000_000 / freq.raw()
+}
+
+/// Converts a pulse width (us) to timer ticks
+fn pulse_as_duty(pulse: u32, period: u32, max_duty: u16) -> u16 {
+    // Duty Cycle = pulse_width / period
+    // Limiting the pulse to 16 bits keeps the product within 32 bits
+    let pulse = pulse.min(period).min(u16::MAX as u32);
+    ((pulse * max_duty as u32 + period / 2) / period) as u16
+}
+
+/// Corrections for an individual servo, applied when converting a position to a pulse.
+/// Positions are measured from the center of the input range, so for continuous servos
+/// positive is forward and negative is reverse.
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct ServoCalibration {
+    /// Pulse width (us) added to every pulse, to move the servo's neutral point to zero
+    pub neutral_offset: i32,
+    /// Width (us) of the servo's deadband on either side of the neutral pulse.
+    /// Nonzero positions are shifted past it so small positions still move the servo.
+    /// Measured in pulse width, so it holds when the input range changes.
+    pub deadband: i32,
+    /// Scale applied to positions above zero
+    pub forward_gain: f32,
+    /// Scale applied to positions below zero
+    pub reverse_gain: f32,
+}
+
+impl Default for ServoCalibration {
+    fn default() -> Self {
+        Self {
+            neutral_offset: 0,
+            deadband: 0,
+            forward_gain: 1.0,
+            reverse_gain: 1.0,
+        }
     }
 }
 
@@ -178,3 +1199,212 @@ impl ServoRanges for InputRange {
         (self.1, self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// PWM output that only records what was written
+    #[derive(Default)]
+    struct MockOutput {
+        pulse: u32,
+        enabled: bool,
+    }
+
+    impl PwmOutput for MockOutput {
+        fn write_pulse(&mut self, pulse: u32) {
+            self.pulse = pulse;
+        }
+
+        fn set_enabled(&mut self, enabled: bool) {
+            self.enabled = enabled;
+        }
+
+        fn period(&self) -> u32 {
+            20_000
+        }
+    }
+
+    /// A servo state with a 1000-2000 us pulse range over `input_range`
+    fn state(input_range: InputRange) -> ServoState {
+        let mut state = ServoState::new();
+        state.set_pulse_range(1000, 2000, 20_000).unwrap();
+        state.set_input_range(input_range).unwrap();
+        state
+    }
+
+    #[test]
+    fn calibration_holds_across_input_ranges() {
+        let calibration = ServoCalibration {
+            neutral_offset: 12,
+            deadband: 40,
+            forward_gain: 1.1,
+            reverse_gain: 0.9,
+        };
+        let mut percent = state(InputRange::CONTINUOUS_RANGE);
+        percent.calibration = calibration;
+        let mut permille = state((-1000, 1000));
+        permille.calibration = calibration;
+
+        for position in [-100, -37, -1, 0, 1, 50, 100] {
+            assert_eq!(
+                percent.position_as_pulse(position),
+                permille.position_as_pulse(10 * position)
+            );
+        }
+    }
+
+    #[test]
+    fn endpoints_and_midpoint() {
+        let positional = state(InputRange::POSITIONAL_RANGE);
+        assert_eq!(positional.position_as_pulse(0), 1000);
+        assert_eq!(positional.position_as_pulse(90), 1500);
+        assert_eq!(positional.position_as_pulse(180), 2000);
+        assert_eq!(positional.position_as_pulse(45), 1250);
+    }
+
+    #[test]
+    fn odd_range_center_is_exact() {
+        let mut odd = state((0, 3));
+        assert_eq!(odd.position_as_pulse(0), 1000);
+        assert_eq!(odd.position_as_pulse(3), 2000);
+        assert_eq!(odd.reset(), 1);
+        assert_eq!(odd.position_as_pulse(1), 1333);
+    }
+
+    #[test]
+    fn inverted_range_mirrors_pulses() {
+        let forward = state(InputRange::CONTINUOUS_RANGE);
+        let reversed = state(InputRange::CONTINUOUS_RANGE.rev());
+        assert_eq!(reversed.position_as_pulse(100), 1000);
+        assert_eq!(reversed.position_as_pulse(-100), 2000);
+        assert_eq!(reversed.position_as_pulse(0), 1500);
+        for position in [-100, -30, 0, 30, 100] {
+            assert_eq!(
+                reversed.position_as_pulse(position),
+                forward.position_as_pulse(-position)
+            );
+        }
+    }
+
+    #[test]
+    fn inverted_range_applies_forward_gain_to_positive_positions() {
+        let mut reversed = state(InputRange::CONTINUOUS_RANGE.rev());
+        reversed.calibration.forward_gain = 0.5;
+        assert_eq!(reversed.position_as_pulse(100), 1250);
+        assert_eq!(reversed.position_as_pulse(-100), 2000);
+    }
+
+    #[test]
+    fn deadband_is_skipped() {
+        let mut continuous = state(InputRange::CONTINUOUS_RANGE);
+        continuous.calibration.deadband = 50;
+        assert_eq!(continuous.position_as_pulse(0), 1500);
+        // The smallest positions start just past the deadband
+        assert_eq!(continuous.position_as_pulse(2), 1559);
+        assert_eq!(continuous.position_as_pulse(-2), 1441);
+        assert_eq!(continuous.position_as_pulse(50), 1775);
+        // Full speed is unchanged
+        assert_eq!(continuous.position_as_pulse(100), 2000);
+        assert_eq!(continuous.position_as_pulse(-100), 1000);
+    }
+
+    #[test]
+    fn neutral_offset_shifts_every_pulse() {
+        let mut continuous = state(InputRange::CONTINUOUS_RANGE);
+        continuous.calibration.neutral_offset = -20;
+        assert_eq!(continuous.position_as_pulse(0), 1480);
+        assert_eq!(continuous.position_as_pulse(100), 1980);
+    }
+
+    #[test]
+    fn pulse_converts_back_to_position() {
+        let positional = state(InputRange::POSITIONAL_RANGE);
+        for position in [0, 1, 45, 90, 179, 180] {
+            let pulse = positional.position_as_pulse(position) as i32;
+            assert_eq!(positional.pulse_as_position(pulse), position);
+        }
+    }
+
+    #[test]
+    fn widest_ranges_do_not_overflow() {
+        let mut wide = ServoState::new();
+        wide.set_pulse_range(500, 500 + MAX_PULSE_RANGE, 20_000)
+            .unwrap();
+        wide.set_input_range((i32::MAX - MAX_SPAN as i32, i32::MAX))
+            .unwrap();
+        wide.calibration.deadband = 10;
+        wide.calibration.forward_gain = 1.5;
+        assert_eq!(wide.position_as_pulse(i32::MAX - MAX_SPAN as i32), 500);
+        assert_eq!(wide.position_as_pulse(i32::MAX), 10_500);
+        assert_eq!(wide.position_as_pulse(i32::MAX - MAX_SPAN as i32 / 2), 5500);
+        assert_eq!(wide.pulse_as_position(10_500), i32::MAX);
+
+        let reversed = state((i32::MIN + MAX_SPAN as i32, i32::MIN));
+        assert_eq!(reversed.position_as_pulse(i32::MIN), 2000);
+        assert_eq!(reversed.position_as_pulse(i32::MIN + MAX_SPAN as i32), 1000);
+    }
+
+    #[test]
+    fn rejects_invalid_ranges_and_pulses() {
+        let mut servo = state(InputRange::POSITIONAL_RANGE);
+        assert_eq!(servo.set_input_range((5, 5)), Err(Error::InvalidRange));
+        assert_eq!(
+            servo.set_input_range((0, MAX_SPAN as i32 + 1)),
+            Err(Error::InvalidRange)
+        );
+        assert_eq!(
+            servo.set_input_range((i32::MIN, i32::MAX)),
+            Err(Error::InvalidRange)
+        );
+        assert_eq!(
+            servo.set_pulse_range(2000, 1000, 20_000),
+            Err(Error::InvalidPulse)
+        );
+        assert_eq!(
+            servo.set_pulse_range(1000, 1000, 20_000),
+            Err(Error::InvalidPulse)
+        );
+        assert_eq!(
+            servo.set_pulse_range(1000, 25_000, 20_000),
+            Err(Error::InvalidPulse)
+        );
+        assert_eq!(
+            servo.set_pulse_range(500, 501 + MAX_PULSE_RANGE, 1_000_000),
+            Err(Error::InvalidPulse)
+        );
+        // A rejected change leaves the servo as it was
+        assert_eq!(servo.input_range, InputRange::POSITIONAL_RANGE);
+        assert_eq!((servo.min_pulse, servo.max_pulse), (1000, 2000));
+    }
+
+    #[test]
+    fn rejects_positions_outside_the_range() {
+        let reversed = state(InputRange::CONTINUOUS_RANGE.rev());
+        assert!(reversed.check_position(-100).is_ok());
+        assert!(reversed.check_position(100).is_ok());
+        assert_eq!(reversed.check_position(101), Err(Error::InvalidPosition));
+        assert_eq!(reversed.check_position(-101), Err(Error::InvalidPosition));
+    }
+
+    #[test]
+    fn resumes_from_stopped_after_detach() {
+        let mut output = MockOutput::default();
+        let mut servo = state(InputRange::CONTINUOUS_RANGE);
+        servo.set_position(&mut output, 80).unwrap();
+        assert_eq!((output.pulse, output.enabled), (1900, true));
+
+        servo.detach(&mut output);
+        assert!(!output.enabled);
+        assert_eq!(servo.position, 0);
+
+        // Ramping back up starts from stopped, not from the speed before coasting
+        servo.set_slew_rate(Some(100.0));
+        servo.set_target(&mut output, 80).unwrap();
+        servo.attach(&mut output);
+        assert_eq!((output.pulse, output.enabled), (1500, true));
+        servo.update(&mut output, 100_000);
+        assert_eq!(servo.position, 10);
+        assert_eq!(output.pulse, 1550);
+    }
+}
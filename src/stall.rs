@@ -0,0 +1,288 @@
+use crate::angle_unit::Radians;
+use crate::mpu6050::wrap;
+use crate::{Angle, ImuMotion, YawPitchRoll};
+use core::f32::consts;
+
+/// Output of a `StallDetector` update
+#[derive(Debug, Copy, Clone, Eq, PartialEq, defmt::Format)]
+pub enum StallStatus {
+    /// The wheels are not commanded to move
+    Idle,
+    /// Commanded to move, and moving or not yet stuck for the whole window
+    Moving,
+    /// Commanded to move, but nothing has moved for the whole window
+    Stalled,
+}
+
+/// Tuning for a `StallDetector`
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct StallConfig {
+    /// Time (us) without motion before the robot is stalled
+    pub window: u32,
+    /// Smallest commanded linear or angular velocity, in wheel speed units, expected to move
+    /// the robot
+    pub min_command: f32,
+    /// Change in yaw, pitch or roll that shows the robot is moving
+    pub min_rotation: Angle<Radians>,
+    /// Angular rate (rad/s) about any axis that shows the robot is moving
+    pub min_rate: f32,
+    /// Acceleration (g) on top of gravity that shows the robot is moving
+    pub min_acceleration: f32,
+    /// Measured ground speed (mm/s) that shows the robot is moving
+    pub min_speed: f32,
+}
+
+impl Default for StallConfig {
+    fn default() -> Self {
+        Self {
+            window: 2_000_000,
+            min_command: 10.0,
+            min_rotation: Angle::new(3.0 * (consts::PI / 180.0)),
+            min_rate: 0.2,
+            min_acceleration: 0.05,
+            min_speed: 20.0,
+        }
+    }
+}
+
+/// Detects the robot being stuck, e.g. on the lip of the wall, by comparing the commanded
+/// motion against the IMU and, when available, a measured ground speed.
+///
+/// While the wheels are commanded to move, something must show motion within each window:
+/// the attitude changing by `min_rotation`, a body rate of `min_rate`, an acceleration of
+/// `min_acceleration` on top of gravity, or a measured speed of `min_speed`. Wheels rolling
+/// up a wall shake the chassis even at a steady attitude, while a robot pinned on the lip
+/// sits still. Tune `min_rate` and `min_acceleration` just above what the IMU reads with the
+/// robot held stuck and the wheels driven.
+pub struct StallDetector {
+    config: StallConfig,
+    /// Attitude (rad) at the start of the window
+    reference: Option<(f32, f32, f32)>,
+    /// Time (us) since the robot last showed motion
+    elapsed: u32,
+}
+
+impl StallDetector {
+    pub fn new(config: StallConfig) -> Self {
+        Self {
+            config,
+            reference: None,
+            elapsed: 0,
+        }
+    }
+
+    pub fn config(&self) -> StallConfig {
+        self.config
+    }
+
+    /// Changing the config restarts the window
+    pub fn set_config(&mut self, config: StallConfig) {
+        *self = Self::new(config);
+    }
+
+    /// Feed the commanded `linear` and `angular` velocities (wheel speed units), the IMU
+    /// `attitude` and `motion`, the measured ground speed (mm/s) if known, and the time (us)
+    /// since the last update. Keeps returning `Stalled` until the robot moves or the command
+    /// stops.
+    pub fn update(
+        &mut self,
+        linear: f32,
+        angular: f32,
+        attitude: YawPitchRoll,
+        motion: ImuMotion,
+        measured_speed: Option<f32>,
+        dt: u32,
+    ) -> StallStatus {
+        let commanded = libm::fabsf(linear).max(libm::fabsf(angular)) >= self.config.min_command;
+        let current = (
+            attitude.yaw.value(),
+            attitude.pitch.value(),
+            attitude.roll.value(),
+        );
+        if !commanded {
+            self.restart(current);
+            return StallStatus::Idle;
+        }
+
+        let reference = *self.reference.get_or_insert(current);
+        let rotation = libm::fabsf(wrap(current.0 - reference.0))
+            .max(libm::fabsf(wrap(current.1 - reference.1)))
+            .max(libm::fabsf(wrap(current.2 - reference.2)));
+        let moving = rotation >= self.config.min_rotation.value()
+            || motion.max_rate() >= self.config.min_rate
+            || motion.dynamic_acceleration() >= self.config.min_acceleration
+            || measured_speed.is_some_and(|speed| libm::fabsf(speed) >= self.config.min_speed);
+        if moving {
+            self.restart(current);
+            return StallStatus::Moving;
+        }
+
+        self.elapsed = self.elapsed.saturating_add(dt);
+        if self.elapsed >= self.config.window {
+            StallStatus::Stalled
+        } else {
+            StallStatus::Moving
+        }
+    }
+
+    /// Forget the window so far, e.g. after a recovery manoeuvre
+    pub fn reset(&mut self) {
+        self.reference = None;
+        self.elapsed = 0;
+    }
+
+    fn restart(&mut self, attitude: (f32, f32, f32)) {
+        self.reference = Some(attitude);
+        self.elapsed = 0;
+    }
+}
+
+impl Default for StallDetector {
+    fn default() -> Self {
+        Self::new(StallConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: u32 = 100_000;
+
+    fn level() -> YawPitchRoll {
+        attitude(0.0)
+    }
+
+    fn attitude(pitch: f32) -> YawPitchRoll {
+        YawPitchRoll {
+            yaw: Angle::new(0.0),
+            pitch: Angle::new(pitch),
+            roll: Angle::new(0.0),
+        }
+    }
+
+    /// Sitting still with gravity straight down
+    fn still() -> ImuMotion {
+        ImuMotion {
+            rate: (0.0, 0.0, 0.0),
+            acceleration: (0.0, 0.0, 1.0),
+        }
+    }
+
+    /// Updates until the window would have run out, returning the last status
+    fn run(detector: &mut StallDetector, motion: ImuMotion, time: u32) -> StallStatus {
+        let mut status = StallStatus::Idle;
+        for _ in 0..time / DT {
+            status = detector.update(100.0, 0.0, level(), motion, None, DT);
+        }
+        status
+    }
+
+    #[test]
+    fn idle_without_a_command() {
+        let mut detector = StallDetector::default();
+        for _ in 0..50 {
+            assert_eq!(
+                detector.update(5.0, -5.0, level(), still(), None, DT),
+                StallStatus::Idle
+            );
+        }
+    }
+
+    #[test]
+    fn stalled_once_the_window_runs_out() {
+        let mut detector = StallDetector::default();
+        let window = detector.config().window;
+
+        assert_eq!(
+            run(&mut detector, still(), window - DT),
+            StallStatus::Moving
+        );
+        assert_eq!(run(&mut detector, still(), DT), StallStatus::Stalled);
+        // Stays stalled while nothing changes
+        assert_eq!(run(&mut detector, still(), window), StallStatus::Stalled);
+    }
+
+    #[test]
+    fn vibration_keeps_a_steady_climb_moving() {
+        let mut detector = StallDetector::default();
+        let climbing = attitude(80.0_f32.to_radians());
+        for i in 0..100 {
+            // Gravity along the body, with the chassis shaking a little as the wheels roll
+            let bump = if i % 5 == 0 { 0.1 } else { 0.0 };
+            let motion = ImuMotion {
+                rate: (0.0, 0.0, 0.0),
+                acceleration: (0.985 + bump, 0.0, 0.17),
+            };
+            assert_eq!(
+                detector.update(100.0, 0.0, climbing, motion, None, DT),
+                StallStatus::Moving
+            );
+        }
+    }
+
+    #[test]
+    fn rates_speed_and_attitude_show_motion() {
+        let window = StallConfig::default().window;
+        let turning = ImuMotion {
+            rate: (0.0, 0.0, -0.5),
+            ..still()
+        };
+
+        let mut detector = StallDetector::default();
+        assert_eq!(run(&mut detector, turning, 2 * window), StallStatus::Moving);
+
+        let mut detector = StallDetector::default();
+        for _ in 0..2 * window / DT {
+            assert_eq!(
+                detector.update(100.0, 0.0, level(), still(), Some(-50.0), DT),
+                StallStatus::Moving
+            );
+        }
+
+        // Tipping slowly, below `min_rate`, still adds up to `min_rotation`
+        let mut detector = StallDetector::default();
+        let mut pitch = 0.0;
+        for _ in 0..2 * window / DT {
+            pitch += 0.5_f32.to_radians();
+            assert_eq!(
+                detector.update(100.0, 0.0, attitude(pitch), still(), None, DT),
+                StallStatus::Moving
+            );
+        }
+    }
+
+    #[test]
+    fn motion_or_stopping_restarts_the_window() {
+        let mut detector = StallDetector::default();
+        let window = detector.config().window;
+        let bump = ImuMotion {
+            acceleration: (0.0, 0.5, 1.0),
+            ..still()
+        };
+
+        assert_eq!(run(&mut detector, still(), window), StallStatus::Stalled);
+        assert_eq!(run(&mut detector, bump, DT), StallStatus::Moving);
+        assert_eq!(
+            run(&mut detector, still(), window - DT),
+            StallStatus::Moving
+        );
+        assert_eq!(run(&mut detector, still(), DT), StallStatus::Stalled);
+
+        assert_eq!(
+            detector.update(0.0, 0.0, level(), still(), None, DT),
+            StallStatus::Idle
+        );
+        assert_eq!(
+            run(&mut detector, still(), window - DT),
+            StallStatus::Moving
+        );
+
+        detector.reset();
+        assert_eq!(
+            run(&mut detector, still(), window - DT),
+            StallStatus::Moving
+        );
+        assert_eq!(run(&mut detector, still(), DT), StallStatus::Stalled);
+    }
+}
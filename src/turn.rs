@@ -0,0 +1,208 @@
+use crate::angle_unit::Radians;
+use crate::mpu6050::wrap;
+use crate::Angle;
+use core::f32::consts;
+
+/// Progress of a `Mikoto::turn_to`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, defmt::Format)]
+pub enum TurnStatus {
+    /// Still turning, or settling within tolerance
+    InProgress,
+    /// Stayed within tolerance of the target for the settle time
+    Done,
+    /// Did not settle on the target before the timeout
+    TimedOut,
+}
+
+/// Tuning for `Mikoto::turn_to`
+#[derive(Debug, Copy, Clone)]
+pub struct TurnConfig {
+    /// Proportional gain, in wheel speed units per radian of heading error
+    pub kp: f32,
+    /// Integral gain, in wheel speed units per radian-second of heading error
+    pub ki: f32,
+    /// Derivative gain, in wheel speed units per radian per second of heading error change
+    pub kd: f32,
+    /// Fastest turning speed, in wheel speed units
+    pub max_speed: f32,
+    /// Heading error within which the robot is on target
+    pub tolerance: Angle<Radians>,
+    /// Time (us) the robot must stay on target to finish
+    pub settle: u32,
+    /// Time (us) after which the turn is abandoned
+    pub timeout: u32,
+}
+
+impl Default for TurnConfig {
+    fn default() -> Self {
+        Self {
+            kp: 60.0,
+            ki: 0.0,
+            kd: 0.0,
+            max_speed: 30.0,
+            tolerance: Angle::new(2.0 * (consts::PI / 180.0)),
+            settle: 250_000,
+            timeout: 10_000_000,
+        }
+    }
+}
+
+/// State of the turn in progress
+pub(crate) struct Turn {
+    config: TurnConfig,
+    /// Contribution of the integral term, in wheel speed units
+    integral: f32,
+    prev_error: Option<f32>,
+    /// Target of the current or last turn, kept once finished so it isn't turned to again
+    target: Option<f32>,
+    status: TurnStatus,
+    elapsed: u32,
+    settled: u32,
+}
+
+impl Turn {
+    pub(crate) fn new(config: TurnConfig) -> Self {
+        Self {
+            config,
+            integral: 0.0,
+            prev_error: None,
+            target: None,
+            status: TurnStatus::InProgress,
+            elapsed: 0,
+            settled: 0,
+        }
+    }
+
+    pub(crate) fn config(&self) -> TurnConfig {
+        self.config
+    }
+
+    /// Changing the config abandons the turn in progress
+    pub(crate) fn set_config(&mut self, config: TurnConfig) {
+        *self = Self::new(config);
+    }
+
+    /// Returns the angular velocity to turn toward `target` from `yaw` (rad), and whether the
+    /// turn has finished. Once finished, the status holds until a new target starts a new turn.
+    pub(crate) fn update(&mut self, yaw: f32, target: f32, dt: u32) -> (f32, TurnStatus) {
+        if self.target != Some(target) {
+            *self = Self::new(self.config);
+            self.target = Some(target);
+        }
+        if self.status != TurnStatus::InProgress {
+            return (0.0, self.status);
+        }
+
+        // Always turn the short way
+        let error = wrap(target - yaw);
+        self.elapsed = self.elapsed.saturating_add(dt);
+        if libm::fabsf(error) <= self.config.tolerance.value() {
+            self.settled = self.settled.saturating_add(dt);
+        } else {
+            self.settled = 0;
+        }
+
+        if self.settled >= self.config.settle {
+            self.status = TurnStatus::Done;
+        } else if self.elapsed >= self.config.timeout {
+            self.status = TurnStatus::TimedOut;
+        } else {
+            return (self.pid(error, dt), TurnStatus::InProgress);
+        }
+        (0.0, self.status)
+    }
+
+    /// Angular velocity for the heading `error` (rad), `dt` (us) after the last update
+    fn pid(&mut self, error: f32, dt: u32) -> f32 {
+        let limit = self.config.max_speed;
+        let dt = dt as f32 / 1_000_000.0;
+        let p = self.config.kp * error;
+
+        let mut d = 0.0;
+        if dt > 0.0 {
+            self.integral = (self.integral + self.config.ki * error * dt).clamp(-limit, limit);
+            if let Some(prev) = self.prev_error {
+                d = self.config.kd * wrap(error - prev) / dt;
+            }
+        }
+        self.prev_error = Some(error);
+
+        (p.clamp(-limit, limit) + self.integral + d.clamp(-limit, limit)).clamp(-limit, limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> TurnConfig {
+        TurnConfig {
+            settle: 20_000,
+            ..TurnConfig::default()
+        }
+    }
+
+    #[test]
+    fn finished_turn_holds_its_status() {
+        let mut turn = Turn::new(config());
+        let target = 0.5;
+        for _ in 0..3 {
+            turn.update(target, target, 10_000);
+        }
+        assert_eq!(turn.update(target, target, 10_000), (0.0, TurnStatus::Done));
+        // Drifting off the target afterwards doesn't restart the turn
+        assert_eq!(turn.update(0.0, target, 10_000), (0.0, TurnStatus::Done));
+        // A new target does
+        assert_eq!(turn.update(0.0, 1.0, 10_000).1, TurnStatus::InProgress);
+    }
+
+    #[test]
+    fn timed_out_turn_holds_its_status() {
+        let mut turn = Turn::new(TurnConfig {
+            timeout: 30_000,
+            ..config()
+        });
+        for _ in 0..3 {
+            turn.update(0.0, 1.0, 10_000);
+        }
+        assert_eq!(turn.update(0.0, 1.0, 10_000), (0.0, TurnStatus::TimedOut));
+        assert_eq!(turn.update(0.0, 1.0, 10_000), (0.0, TurnStatus::TimedOut));
+    }
+
+    #[test]
+    fn integral_scales_with_time() {
+        let config = TurnConfig {
+            kp: 0.0,
+            ki: 10.0,
+            ..config()
+        };
+        let mut fast = Turn::new(config);
+        let mut slow = Turn::new(config);
+        let mut fast_output = 0.0;
+        for _ in 0..10 {
+            fast_output = fast.update(0.0, 0.5, 10_000).0;
+        }
+        let slow_output = slow.update(0.0, 0.5, 100_000).0;
+        // 0.1 s at 0.5 rad error, however it is split into updates
+        assert!((fast_output - 0.5).abs() < 1e-4);
+        assert!((slow_output - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn derivative_scales_with_time() {
+        let config = TurnConfig {
+            kp: 0.0,
+            kd: 1.0,
+            ..config()
+        };
+        let mut fast = Turn::new(config);
+        let mut slow = Turn::new(config);
+        fast.update(0.0, 1.0, 10_000);
+        slow.update(0.0, 1.0, 100_000);
+        // Turning at 1 rad/s
+        let fast_output = fast.update(0.01, 1.0, 10_000).0;
+        let slow_output = slow.update(0.1, 1.0, 100_000).0;
+        assert!((fast_output + 1.0).abs() < 1e-3);
+        assert!((slow_output + 1.0).abs() < 1e-3);
+    }
+}
@@ -1,35 +1,273 @@
+use core::fmt;
+
 use crate::hal::{
     gpio::{Pin, PinMode},
+    nb,
+    prelude::*,
     timer::{CounterUs, Instance},
 };
 
-pub trait Ultrasonic<TIM: Instance, const P1: char, const N1: u8, const P2: char, const N2: u8> {
-    fn new(
-        trigger: Pin<P1, N1, impl PinMode>,
-        echo: Pin<P2, N2, impl PinMode>,
-        counter: CounterUs<TIM>,
-    ) -> Self;
-
-    fn waste(c_us: &CounterUs<TIM>, us: u32) {
-        let ts1 = c_us.now().ticks();
-        while (c_us.now().ticks() - ts1) < us {}
-    }
-
-    fn waste_until<T>(
-        c_us: &CounterUs<TIM>,
-        predicate: fn(_: &T) -> bool,
-        dt: &T,
-        us: u32,
-    ) -> Option<u32> {
-        let ts1 = c_us.now().ticks();
-        while (c_us.now().ticks() - ts1) < us && !predicate(dt) {}
-
-        if predicate(dt) {
-            Some(c_us.now().ticks() - ts1)
+pub trait Ultrasonic<const P1: char, const N1: u8, const P2: char, const N2: u8> {
+    fn new(trigger: Pin<P1, N1, impl PinMode>, echo: Pin<P2, N2, impl PinMode>) -> Self;
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, defmt::Format)]
+pub enum ClockError {
+    /// The timer can't count the whole period, e.g. over 65536 us on a 16-bit timer
+    PeriodTooLong,
+    /// An interval to time is not shorter than the clock's period, so it would wrap
+    IntervalTooLong,
+}
+
+/// Free-running microsecond timebase shared by ultrasonic sensors, so any number of sensors
+/// needs only one timer. Sensors borrow it to timestamp triggers and echo edges.
+///
+/// The counter wraps every `period` (us), so only intervals shorter than the period can be
+/// timed, and a measurement must be polled at least once a period. 16-bit timers (all but TIM2
+/// and TIM5 on the STM32F401) count at most 65536 us, which fits an HC-SR04 at its default
+/// range and interval. A URM37 at its full range needs a 32-bit timer or a shorter
+/// `set_max_range`.
+pub struct EchoClock<TIM: Instance> {
+    counter: CounterUs<TIM>,
+    period: u32,
+}
+
+impl<TIM: Instance> EchoClock<TIM> {
+    /// Longest period (us) of a 16-bit timer
+    pub const MAX_16_BIT_PERIOD: u32 = 65_536;
+
+    /// Start the counter wrapping every `period` (us). Fails if the timer can't count that far.
+    pub fn new(mut counter: CounterUs<TIM>, period: u32) -> Result<Self, ClockError> {
+        counter
+            .start(period.micros())
+            .map_err(|_| ClockError::PeriodTooLong)?;
+        Ok(Self { counter, period })
+    }
+
+    /// Time (us) after which the clock wraps
+    pub fn period(&self) -> u32 {
+        self.period
+    }
+
+    /// Current timestamp (us), wrapping to zero every period
+    pub fn now(&self) -> u32 {
+        self.counter.now().ticks()
+    }
+
+    /// Time (us) since the timestamp `since`, assuming less than a period has passed
+    pub fn elapsed(&self, since: u32) -> u32 {
+        wrapping_elapsed(since, self.now(), self.period)
+    }
+
+    /// Fails unless an interval of `us` can be timed without wrapping
+    pub fn check_interval(&self, us: u32) -> Result<(), ClockError> {
+        if us < self.period {
+            Ok(())
+        } else {
+            Err(ClockError::IntervalTooLong)
+        }
+    }
+
+    /// Busy wait for `us`
+    pub fn delay(&self, us: u32) {
+        let start = self.now();
+        while self.elapsed(start) < us {}
+    }
+
+    /// Busy wait up to `us` for `predicate` to hold on `value`. Returns the time (us) waited,
+    /// or `None` on timeout.
+    pub fn wait_until<T>(&self, predicate: fn(_: &T) -> bool, value: &T, us: u32) -> Option<u32> {
+        let start = self.now();
+        while self.elapsed(start) < us && !predicate(value) {}
+
+        if predicate(value) {
+            Some(self.elapsed(start))
         } else {
             None
         }
     }
+
+    /// Returns the counter, consuming the clock
+    pub fn release(self) -> CounterUs<TIM> {
+        self.counter
+    }
+}
+
+/// Time (us) from `from` to `to` on a counter that wraps to zero every `period`
+fn wrapping_elapsed(from: u32, to: u32, period: u32) -> u32 {
+    if to >= from {
+        to - from
+    } else {
+        period - from + to
+    }
+}
+
+/// Non-blocking echo measurement driven by the echo pin's external interrupt, which the
+/// sensor's `enable_interrupt` sets up.
+///
+/// Edges are timestamped with the shared `EchoClock` from inside the interrupt handler,
+/// so the measured pulse does not depend on how busy the main loop is.
+pub trait EchoTiming<TIM: Instance> {
+    /// Send a trigger pulse and start timing the echo.
+    /// Returns `WouldBlock` until the sensor's minimum interval since the last trigger has passed,
+    /// and `ClockTooShort` if the clock would wrap during the measurement cycle.
+    fn trigger(&mut self, clock: &EchoClock<TIM>) -> nb::Result<(), RangeError>;
+
+    /// Timestamp an echo pin edge and clear its pending interrupt bit.
+    /// Must be called from the echo pin's EXTI interrupt handler.
+    fn on_echo_edge(&mut self, clock: &EchoClock<TIM>);
+
+    /// Returns the echo pulse duration (in us) once the echo has ended, or `WouldBlock` while
+    /// the measurement is still in progress.
+    fn poll(&mut self, clock: &EchoClock<TIM>) -> nb::Result<u32, RangeError>;
+}
+
+impl<TIM: Instance, T: EchoTiming<TIM> + ?Sized> EchoTiming<TIM> for &mut T {
+    fn trigger(&mut self, clock: &EchoClock<TIM>) -> nb::Result<(), RangeError> {
+        (**self).trigger(clock)
+    }
+
+    fn on_echo_edge(&mut self, clock: &EchoClock<TIM>) {
+        (**self).on_echo_edge(clock)
+    }
+
+    fn poll(&mut self, clock: &EchoClock<TIM>) -> nb::Result<u32, RangeError> {
+        (**self).poll(clock)
+    }
+}
+
+/// An ultrasonic sensor borrowed along with the `EchoClock` it is timed by, for use as a
+/// `RangeSensor`
+pub struct Clocked<'a, S, TIM: Instance> {
+    pub(crate) sensor: &'a mut S,
+    pub(crate) clock: &'a EchoClock<TIM>,
+}
+
+impl<'a, S, TIM: Instance> Clocked<'a, S, TIM> {
+    pub fn new(sensor: &'a mut S, clock: &'a EchoClock<TIM>) -> Self {
+        Self { sensor, clock }
+    }
+}
+
+/// A distance sensor, so behaviours like wall following work with any sensor
+pub trait RangeSensor {
+    /// Measure the range (mm) to the nearest target, waiting for the measurement to finish
+    fn read_mm(&mut self) -> Result<f32, RangeError>;
+
+    /// Start a measurement without waiting for it.
+    /// Returns `WouldBlock` until the sensor is ready for another measurement.
+    fn start(&mut self) -> nb::Result<(), RangeError>;
+
+    /// Returns the range (mm) measured since `start`, or `WouldBlock` while the measurement
+    /// is still in progress.
+    fn poll_mm(&mut self) -> nb::Result<f32, RangeError>;
+}
+
+impl<T: RangeSensor + ?Sized> RangeSensor for &mut T {
+    fn read_mm(&mut self) -> Result<f32, RangeError> {
+        (**self).read_mm()
+    }
+
+    fn start(&mut self) -> nb::Result<(), RangeError> {
+        (**self).start()
+    }
+
+    fn poll_mm(&mut self) -> nb::Result<f32, RangeError> {
+        (**self).poll_mm()
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, defmt::Format)]
+pub enum RangeError {
+    /// No echo was received
+    Timeout,
+    /// Echo is longer than the sensor's configured maximum range
+    OutOfRange,
+    /// The `EchoClock` wraps before the sensor's measurement cycle ends
+    ClockTooShort,
+}
+
+/// Progress of an interrupt-driven echo measurement
+#[derive(Debug, Clone, Copy, Eq, PartialEq, defmt::Format)]
+pub enum EchoCapture {
+    /// No measurement in progress
+    Idle,
+    /// Trigger sent, waiting for the echo pulse to start
+    Waiting,
+    /// Echo pulse started at the given counter timestamp (us)
+    Started(u32),
+    /// Echo pulse ended with the given duration (us)
+    Complete(u32),
+}
+
+impl EchoCapture {
+    /// Advance the measurement on an echo edge.
+    /// `active` is true if the edge starts the echo pulse, false if it ends it.
+    pub fn edge(&mut self, active: bool, now: u32) {
+        *self = match *self {
+            Self::Waiting if active => Self::Started(now),
+            Self::Started(start) if !active => Self::Complete(now.wrapping_sub(start)),
+            capture => capture,
+        }
+    }
+
+    /// Returns the pulse duration of a completed measurement and resets to idle.
+    /// `now` is the counter timestamp (us) since the trigger, and `max_pulse` is the longest
+    /// accepted echo, which is also how long to wait for the echo to start.
+    pub fn take(&mut self, now: u32, max_pulse: u32) -> nb::Result<u32, RangeError> {
+        let result = match *self {
+            Self::Idle => return Err(nb::Error::WouldBlock),
+            Self::Waiting if now > max_pulse => Err(RangeError::Timeout),
+            Self::Started(start) if now.wrapping_sub(start) > max_pulse => {
+                Err(RangeError::OutOfRange)
+            }
+            Self::Waiting | Self::Started(_) => return Err(nb::Error::WouldBlock),
+            Self::Complete(duration) if duration > max_pulse => Err(RangeError::OutOfRange),
+            Self::Complete(duration) => Ok(duration),
+        };
+        *self = Self::Idle;
+        result.map_err(nb::Error::Other)
+    }
+}
+
+/// Ambient air temperature, used to correct the speed of sound in distance conversions
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Temperature(f32);
+
+impl Temperature {
+    /// Room temperature (20°C)
+    pub const ROOM: Temperature = Temperature(20.0);
+
+    pub const fn from_celsius(celsius: f32) -> Temperature {
+        Temperature(celsius)
+    }
+
+    pub fn celsius(&self) -> f32 {
+        self.0
+    }
+
+    /// Speed of sound in dry air (m/s) at this temperature
+    pub fn speed_of_sound(&self) -> f32 {
+        331.3 * libm::sqrtf(1.0 + self.0 / 273.15)
+    }
+
+    /// Round trip echo time (us) for a target 1 cm away.
+    // 2 cm / (speed m/s * 100 cm/m) * 10^6 us/s = 20000 / speed
+    pub fn pulse_per_cm(&self) -> f32 {
+        20_000.0 / self.speed_of_sound()
+    }
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}°C", self.0)
+    }
+}
+
+impl defmt::Format for Temperature {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}°C", self.0);
+    }
 }
 
 /// Units to describe distance
@@ -54,3 +292,37 @@ pub mod unit {
         const UNIT: &'static str = "in";
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elapsed_across_the_wrap() {
+        assert_eq!(wrapping_elapsed(100, 350, 65_536), 250);
+        assert_eq!(wrapping_elapsed(65_000, 200, 65_536), 736);
+        assert_eq!(wrapping_elapsed(0, 65_535, 65_536), 65_535);
+        assert_eq!(wrapping_elapsed(7, 7, 65_536), 0);
+    }
+
+    #[test]
+    fn capture_times_the_echo_from_the_trigger() {
+        let mut capture = EchoCapture::Waiting;
+        assert_eq!(capture.take(100, 1_000), Err(nb::Error::WouldBlock));
+        capture.edge(true, 300);
+        capture.edge(false, 880);
+        assert_eq!(capture.take(900, 1_000), Ok(580));
+        assert_eq!(capture, EchoCapture::Idle);
+
+        let mut capture = EchoCapture::Waiting;
+        assert_eq!(
+            capture.take(1_001, 1_000),
+            Err(nb::Error::Other(RangeError::Timeout))
+        );
+        let mut capture = EchoCapture::Started(100);
+        assert_eq!(
+            capture.take(1_200, 1_000),
+            Err(nb::Error::Other(RangeError::OutOfRange))
+        );
+    }
+}
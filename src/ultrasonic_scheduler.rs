@@ -0,0 +1,115 @@
+use crate::hal::{nb, timer::Instance};
+use crate::ultrasonic::{ClockError, EchoClock, EchoTiming, RangeError};
+
+/// Fires a set of ultrasonic sensors one at a time, so they don't hear each other's pings.
+///
+/// The scheduler owns the `EchoClock` that times all of its sensors, so the whole set needs
+/// only one timer. Borrow it with `clock` to time sensors outside the schedule.
+///
+/// Sensors with different pins are different types, so pass them as `&mut dyn EchoTiming`.
+/// Every echo pin's EXTI interrupt handler must call `on_echo_edge`.
+pub struct UltrasonicScheduler<S: EchoTiming<TIM>, TIM: Instance, const N: usize> {
+    sensors: [S; N],
+    readings: [Option<Result<u32, RangeError>>; N],
+    active: Option<usize>,
+    next: usize,
+    clock: EchoClock<TIM>,
+    guard: u32,
+    /// Clock timestamp (us) of the end of the last measurement
+    guard_start: Option<u32>,
+}
+
+impl<S: EchoTiming<TIM>, TIM: Instance, const N: usize> UltrasonicScheduler<S, TIM, N> {
+    /// `guard` is the time (us) to wait after one sensor's measurement before triggering the
+    /// next, giving stray echoes time to die out. Fails if the guard is as long as the clock's
+    /// period.
+    pub fn new(sensors: [S; N], clock: EchoClock<TIM>, guard: u32) -> Result<Self, ClockError> {
+        clock.check_interval(guard)?;
+        Ok(Self {
+            sensors,
+            readings: [None; N],
+            active: None,
+            next: 0,
+            clock,
+            guard,
+            guard_start: None,
+        })
+    }
+
+    /// Advance the schedule. Call this frequently from the main loop, and at least once per
+    /// clock period.
+    pub fn update(&mut self) {
+        match self.active {
+            Some(i) => {
+                let reading = match self.sensors[i].poll(&self.clock) {
+                    Err(nb::Error::WouldBlock) => return,
+                    Err(nb::Error::Other(e)) => Err(e),
+                    Ok(pulse) => Ok(pulse),
+                };
+                self.readings[i] = Some(reading);
+                self.active = None;
+                self.guard_start = Some(self.clock.now());
+            }
+            None => {
+                if N == 0 || !self.guard_elapsed() {
+                    return;
+                }
+                let i = self.next;
+                match self.sensors[i].trigger(&self.clock) {
+                    Ok(()) => self.active = Some(i),
+                    Err(nb::Error::WouldBlock) => return,
+                    // Can't be measured with this clock, move on to the next sensor
+                    Err(nb::Error::Other(e)) => self.readings[i] = Some(Err(e)),
+                }
+                self.next = (i + 1) % N;
+            }
+        }
+    }
+
+    /// Forward an echo pin edge to the sensors. Call from every echo pin's EXTI interrupt handler.
+    pub fn on_echo_edge(&mut self) {
+        // Only the active sensor is waiting for an echo, the others just clear their pending bit.
+        for sensor in self.sensors.iter_mut() {
+            sensor.on_echo_edge(&self.clock);
+        }
+    }
+
+    /// Latest echo pulse duration (us) measured by the sensor at `index`, or `None` if it
+    /// hasn't finished a measurement yet.
+    pub fn reading(&self, index: usize) -> Option<Result<u32, RangeError>> {
+        self.readings[index]
+    }
+
+    /// Latest echo pulse durations (us) of all sensors
+    pub fn readings(&self) -> &[Option<Result<u32, RangeError>>; N] {
+        &self.readings
+    }
+
+    /// Set the time (us) between one sensor's measurement and the next trigger. Fails if the
+    /// guard is as long as the clock's period.
+    pub fn set_guard(&mut self, guard: u32) -> Result<(), ClockError> {
+        self.clock.check_interval(guard)?;
+        self.guard = guard;
+        Ok(())
+    }
+
+    /// The timebase shared by the sensors
+    pub fn clock(&self) -> &EchoClock<TIM> {
+        &self.clock
+    }
+
+    /// Returns the sensors and clock, consuming the scheduler
+    pub fn release(self) -> ([S; N], EchoClock<TIM>) {
+        (self.sensors, self.clock)
+    }
+
+    fn guard_elapsed(&mut self) -> bool {
+        match self.guard_start {
+            Some(start) if self.clock.elapsed(start) < self.guard => false,
+            _ => {
+                self.guard_start = None;
+                true
+            }
+        }
+    }
+}
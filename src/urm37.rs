@@ -1,58 +1,314 @@
 use crate::hal::{
-    gpio::{Input, Output, Pin, PinMode, PushPull},
+    gpio::{Edge, ExtiPin, Input, Output, Pin, PinMode, PushPull},
+    nb,
+    pac::EXTI,
     prelude::*,
+    serial,
+    syscfg::SysCfg,
     timer::{CounterUs, Instance},
 };
-use crate::{distance_unit::*, Ultrasonic};
+use crate::ultrasonic::{Clocked, EchoCapture, EchoClock, EchoTiming, RangeError, RangeSensor};
+use crate::{distance_unit::*, Temperature, Ultrasonic, Usart};
 use core::{cmp::Ordering, fmt, marker::PhantomData};
 
-/// URM37 v5.0 Ultrasonic sensor
-pub struct Urm37<TIM: Instance, const P1: char, const N1: u8, const P2: char, const N2: u8> {
+/// URM37 v5.0 Ultrasonic sensor. Timed by a borrowed `EchoClock`, which any number of sensors
+/// can share.
+///
+/// An echo at the full 800 cm range takes longer than a 16-bit timer can count, so use a 32-bit
+/// timer for the clock or a shorter `set_max_range`.
+pub struct Urm37<const P1: char, const N1: u8, const P2: char, const N2: u8> {
     trigger: Pin<P1, N1, Output<PushPull>>,
     echo: Pin<P2, N2, Input>,
-    counter: CounterUs<TIM>,
+    capture: EchoCapture,
+    max_pulse: u32,
+    min_interval: u32,
+    /// Clock timestamp (us) of the last trigger
+    cycle_start: Option<u32>,
 }
-impl<TIM: Instance, const P1: char, const N1: u8, const P2: char, const N2: u8>
-    Ultrasonic<TIM, P1, N1, P2, N2> for Urm37<TIM, P1, N1, P2, N2>
+impl<const P1: char, const N1: u8, const P2: char, const N2: u8> Ultrasonic<P1, N1, P2, N2>
+    for Urm37<P1, N1, P2, N2>
 {
-    fn new(
-        trigger: Pin<P1, N1, impl PinMode>,
-        echo: Pin<P2, N2, impl PinMode>,
-        counter: CounterUs<TIM>,
-    ) -> Self {
+    fn new(trigger: Pin<P1, N1, impl PinMode>, echo: Pin<P2, N2, impl PinMode>) -> Self {
         let trigger = trigger.into_push_pull_output();
         let echo = echo.into_pull_down_input();
         Self {
             trigger,
             echo,
-            counter,
+            capture: EchoCapture::Idle,
+            max_pulse: Distance::<Cm>::new(Self::MAX_RANGE).as_pulse().value(),
+            min_interval: Self::MIN_INTERVAL,
+            cycle_start: None,
         }
     }
 }
 
-impl<TIM: Instance, const P1: char, const N1: u8, const P2: char, const N2: u8>
-    Urm37<TIM, P1, N1, P2, N2>
-{
-    pub fn read(&mut self) -> Option<Distance<PulseDuration>> {
-        // 1 second timeout
-        self.counter.start(1_000_000_u32.micros()).unwrap();
+impl<const P1: char, const N1: u8, const P2: char, const N2: u8> Urm37<P1, N1, P2, N2> {
+    /// Maximum range of the sensor (cm)
+    const MAX_RANGE: u32 = 800;
+    /// Minimum time (us) between triggers
+    const MIN_INTERVAL: u32 = 50_000;
+
+    pub fn read<TIM: Instance>(
+        &mut self,
+        clock: &EchoClock<TIM>,
+    ) -> Result<Distance<PulseDuration>, RangeError> {
+        self.check_cycle(clock)?;
+        // Wait for the previous measurement cycle to end
+        while !self.interval_elapsed(clock) {}
+        self.cycle_start = Some(clock.now());
+
+        self.send_trigger(clock);
+
+        // starting echo read
+        clock
+            .wait_until(|c| c.is_low(), &self.echo, self.max_pulse)
+            .ok_or(RangeError::Timeout)?;
+        let pulse_duration = clock
+            .wait_until(|c| c.is_high(), &self.echo, self.max_pulse)
+            .ok_or(RangeError::OutOfRange)?;
+
+        let distance: Distance<PulseDuration> = Distance(pulse_duration, PhantomData);
+        Ok(distance)
+    }
+
+    /// Enable the external interrupt on both edges of the echo pin, for `EchoTiming`
+    pub fn enable_interrupt(&mut self, syscfg: &mut SysCfg, exti: &mut EXTI) {
+        self.echo.make_interrupt_source(syscfg);
+        self.echo.trigger_on_edge(exti, Edge::RisingFalling);
+        self.echo.enable_interrupt(exti);
+    }
+
+    /// Reject echoes from targets further than `range`.
+    /// A shorter range also shortens the wait for a missing echo.
+    pub fn set_max_range(&mut self, range: Distance<Cm>) {
+        self.max_pulse = range.as_pulse().value();
+    }
+
+    pub fn max_range(&self) -> Distance<Cm> {
+        Distance::<PulseDuration>(self.max_pulse, PhantomData).as_cm()
+    }
+
+    /// Set the minimum time (us) between triggers, so a late echo from the previous
+    /// ping is not mistaken for the current one.
+    pub fn set_min_interval(&mut self, us: u32) {
+        self.min_interval = us;
+    }
+
+    /// Fails if `clock` would wrap before the echo times out or the next trigger is due
+    fn check_cycle<TIM: Instance>(&self, clock: &EchoClock<TIM>) -> Result<(), RangeError> {
+        // The echo can start as late as `max_pulse` and then last as long again
+        clock
+            .check_interval(self.min_interval.max(2 * self.max_pulse))
+            .map_err(|_| RangeError::ClockTooShort)
+    }
 
+    /// Whether the minimum interval since the last trigger has passed
+    fn interval_elapsed<TIM: Instance>(&self, clock: &EchoClock<TIM>) -> bool {
+        self.cycle_start
+            .is_none_or(|start| clock.elapsed(start) >= self.min_interval)
+    }
+
+    /// Time (us) since the last trigger
+    fn since_trigger<TIM: Instance>(&self, clock: &EchoClock<TIM>) -> u32 {
+        self.cycle_start.map_or(0, |start| clock.elapsed(start))
+    }
+
+    fn send_trigger<TIM: Instance>(&mut self, clock: &EchoClock<TIM>) {
         // starting pulse
         self.trigger.set_high();
-        Self::waste(&self.counter, 2);
+        clock.delay(2);
         self.trigger.set_low();
-        Self::waste(&self.counter, 10);
+        clock.delay(10);
         self.trigger.set_high();
         // ending pulse
+    }
+}
 
-        // starting echo read
-        Self::waste_until(&self.counter, |c| c.is_low(), &self.echo, 1_000_000)?;
-        let pulse_duration =
-            Self::waste_until(&self.counter, |c| c.is_high(), &self.echo, 1_000_000)?;
+impl<TIM: Instance, const P1: char, const N1: u8, const P2: char, const N2: u8> EchoTiming<TIM>
+    for Urm37<P1, N1, P2, N2>
+{
+    fn trigger(&mut self, clock: &EchoClock<TIM>) -> nb::Result<(), RangeError> {
+        self.check_cycle(clock)?;
+        if !self.interval_elapsed(clock) {
+            return Err(nb::Error::WouldBlock);
+        }
+        // Timestamps are taken relative to the trigger
+        self.cycle_start = Some(clock.now());
+        self.capture = EchoCapture::Waiting;
+        self.send_trigger(clock);
+        Ok(())
+    }
 
+    fn on_echo_edge(&mut self, clock: &EchoClock<TIM>) {
+        let now = self.since_trigger(clock);
+        self.echo.clear_interrupt_pending_bit();
+        self.capture.edge(self.echo.is_low(), now);
+    }
+
+    fn poll(&mut self, clock: &EchoClock<TIM>) -> nb::Result<u32, RangeError> {
+        let now = self.since_trigger(clock);
+        self.capture.take(now, self.max_pulse)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, defmt::Format)]
+pub enum Error {
+    /// USART was created without a receive pin
+    NoRx,
+    /// USART read or write failed
+    Serial,
+    /// No response from the sensor
+    Timeout,
+    /// Response checksum does not match its contents
+    Checksum,
+    /// Response does not belong to the command that was sent
+    InvalidResponse,
+    /// Sensor could not take a measurement
+    InvalidReading,
+    /// Counter can't time the response, e.g. on a 16-bit timer
+    Timer,
+}
+
+/// URM37 v5.0 Ultrasonic sensor in serial (TTL) mode.
+///
+/// The USART must be created with a receive pin at 9600 baud (see `Usart::with_rx`). The
+/// response timeout is longer than a 16-bit timer can count, so the counter needs a 32-bit timer.
+pub struct Urm37Serial<USART: serial::Instance, TIM: Instance> {
+    usart: Usart<USART>,
+    counter: CounterUs<TIM>,
+}
+
+impl<USART: serial::Instance, TIM: Instance> Urm37Serial<USART, TIM> {
+    /// Maximum time (us) to wait for a response. A distance measurement takes the longest.
+    const TIMEOUT: u32 = 200_000;
+
+    const READ_TEMPERATURE: u8 = 0x11;
+    const READ_DISTANCE: u8 = 0x22;
+    const READ_EEPROM: u8 = 0x33;
+    const WRITE_EEPROM: u8 = 0x44;
+
+    pub fn new(usart: Usart<USART>, counter: CounterUs<TIM>) -> Self {
+        Self { usart, counter }
+    }
+
+    /// Returns the USART and counter, consuming the driver
+    pub fn release(self) -> (Usart<USART>, CounterUs<TIM>) {
+        (self.usart, self.counter)
+    }
+
+    /// Take a distance measurement
+    pub fn read_distance(&mut self) -> Result<Distance<Cm>, Error> {
+        // Second byte sets the angle of a servo driven by the sensor, which is unused.
+        let [_, high, low] = self.command([Self::READ_DISTANCE, 0x00, 0x00])?;
+        match u16::from_be_bytes([high, low]) {
+            0xFFFF => Err(Error::InvalidReading),
+            cm => Ok(Distance(cm as u32, PhantomData)),
+        }
+    }
+
+    /// Read the sensor's built-in temperature sensor
+    pub fn read_temperature(&mut self) -> Result<Temperature, Error> {
+        let [_, high, low] = self.command([Self::READ_TEMPERATURE, 0x00, 0x00])?;
+        if high == 0xFF && low == 0xFF {
+            return Err(Error::InvalidReading);
+        }
+        // 12 bit magnitude in 0.1°C, the upper 4 bits are all set when below zero
+        let celsius = u16::from_be_bytes([high & 0x0F, low]) as f32 / 10.0;
+        if high & 0xF0 == 0xF0 {
+            Ok(Temperature::from_celsius(-celsius))
+        } else {
+            Ok(Temperature::from_celsius(celsius))
+        }
+    }
+
+    /// Read a byte from the sensor's configuration EEPROM
+    pub fn read_eeprom(&mut self, address: u8) -> Result<u8, Error> {
+        let [_, read_address, data] = self.command([Self::READ_EEPROM, address, 0x00])?;
+        if read_address != address {
+            return Err(Error::InvalidResponse);
+        }
+        Ok(data)
+    }
+
+    /// Write a byte to the sensor's configuration EEPROM
+    pub fn write_eeprom(&mut self, address: u8, data: u8) -> Result<(), Error> {
+        let response = self.command([Self::WRITE_EEPROM, address, data])?;
+        if response != [Self::WRITE_EEPROM, address, data] {
+            return Err(Error::InvalidResponse);
+        }
+        Ok(())
+    }
+
+    /// Send a command and return the first three bytes of the validated response
+    fn command(&mut self, command: [u8; 3]) -> Result<[u8; 3], Error> {
+        let rx = self.usart.rx().ok_or(Error::NoRx)?;
+        // Discard any stale bytes from an earlier response. Reading also clears an overrun or
+        // other receive error, so keep going until the receiver is empty.
+        while !matches!(rx.read(), Err(nb::Error::WouldBlock)) {}
+
+        let [c0, c1, c2] = command;
+        self.usart
+            .tx()
+            .bwrite_all(&[c0, c1, c2, checksum(&command)])
+            .map_err(|_| Error::Serial)?;
+
+        let mut response = [0_u8; 4];
+        self.counter
+            .start((2 * Self::TIMEOUT).micros())
+            .map_err(|_| Error::Timer)?;
+        let mut received = 0;
+        while received < response.len() {
+            let rx = self.usart.rx().ok_or(Error::NoRx)?;
+            match rx.read() {
+                Ok(byte) => {
+                    response[received] = byte;
+                    received += 1;
+                }
+                Err(nb::Error::WouldBlock) => {
+                    if self.counter.now().ticks() > Self::TIMEOUT {
+                        self.counter.cancel().unwrap();
+                        return Err(Error::Timeout);
+                    }
+                }
+                Err(nb::Error::Other(_)) => {
+                    self.counter.cancel().unwrap();
+                    return Err(Error::Serial);
+                }
+            }
+        }
         self.counter.cancel().unwrap();
-        let distance: Distance<PulseDuration> = Distance(pulse_duration, PhantomData);
-        Some(distance)
+
+        let [r0, r1, r2, sum] = response;
+        if checksum(&[r0, r1, r2]) != sum {
+            return Err(Error::Checksum);
+        }
+        if r0 != c0 {
+            return Err(Error::InvalidResponse);
+        }
+        Ok([r0, r1, r2])
+    }
+}
+
+/// Serial mode checksum: low 8 bits of the sum of all preceding bytes
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, b| sum.wrapping_add(*b))
+}
+
+impl<TIM: Instance, const P1: char, const N1: u8, const P2: char, const N2: u8> RangeSensor
+    for Clocked<'_, Urm37<P1, N1, P2, N2>, TIM>
+{
+    fn read_mm(&mut self) -> Result<f32, RangeError> {
+        let pulse = self.sensor.read(self.clock)?;
+        Ok(pulse.value() as f32 * 10.0 / 50.0)
+    }
+
+    fn start(&mut self) -> nb::Result<(), RangeError> {
+        self.sensor.trigger(self.clock)
+    }
+
+    fn poll_mm(&mut self) -> nb::Result<f32, RangeError> {
+        let pulse = self.sensor.poll(self.clock)?;
+        Ok(pulse as f32 * 10.0 / 50.0)
     }
 }
 
@@ -2,12 +2,14 @@ use crate::hal::{
     gpio::NoPin,
     prelude::*,
     rcc::Clocks,
-    serial::{Config, Instance, Pins, Tx},
+    serial::{Config, Instance, Pins, Rx, Tx},
+    time::Bps,
 };
 
 /// USART
 pub struct Usart<USART: Instance> {
     tx: Tx<USART>,
+    rx: Option<Rx<USART>>,
 }
 
 // On Nucleo-f401re: tx_pin = PA2
@@ -26,11 +28,43 @@ impl<USART: Instance> Usart<USART> {
                 clocks,
             )
             .unwrap();
-        Self { tx }
+        Self { tx, rx: None }
+    }
+
+    /// Full-duplex USART with a receive pin and custom baud rate.
+    // On Nucleo-f401re: tx_pin = PA2, rx_pin = PA3
+    pub fn with_rx<TX, RX>(
+        tx_pin: TX,
+        rx_pin: RX,
+        usart: USART,
+        baudrate: Bps,
+        clocks: &Clocks,
+    ) -> Self
+    where
+        (TX, RX): Pins<USART>,
+    {
+        let (tx, rx) = usart
+            .serial(
+                (tx_pin, rx_pin),
+                Config::default()
+                    .baudrate(baudrate)
+                    .wordlength_8()
+                    .parity_none(),
+                clocks,
+            )
+            .unwrap()
+            .split();
+        Self { tx, rx: Some(rx) }
     }
 
     // Write to usart: writeln!(usart.tx(), "{}", s).unwrap();
     pub fn tx(&mut self) -> &mut Tx<USART> {
         &mut self.tx
     }
+
+    // Read from usart: block!(usart.rx().unwrap().read()).unwrap();
+    /// Returns `None` if the USART was created without a receive pin.
+    pub fn rx(&mut self) -> Option<&mut Rx<USART>> {
+        self.rx.as_mut()
+    }
 }
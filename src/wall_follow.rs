@@ -0,0 +1,211 @@
+use crate::angle_unit::Radians;
+use crate::mpu6050::wrap;
+use crate::Angle;
+use core::f32::consts;
+
+/// Side of the robot the wall is on
+#[derive(Debug, Copy, Clone, Eq, PartialEq, defmt::Format)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// Tuning for a `WallFollow`
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct WallFollowConfig {
+    /// Side the range sensor faces, square to the robot's heading
+    pub side: Side,
+    /// Distance (mm) to hold from the wall, measured from the sensor
+    pub distance: f32,
+    /// Heading correction (rad) per mm of distance error
+    pub kp: f32,
+    /// Largest heading correction toward or away from the wall
+    pub max_angle: Angle<Radians>,
+    /// Readings beyond this range (mm) mean the wall is lost, e.g. at a gap or corner
+    pub max_range: f32,
+}
+
+impl Default for WallFollowConfig {
+    fn default() -> Self {
+        Self {
+            side: Side::Right,
+            distance: 150.0,
+            kp: 0.2 * (consts::PI / 180.0),
+            max_angle: Angle::new(20.0 * (consts::PI / 180.0)),
+            max_range: 500.0,
+        }
+    }
+}
+
+/// Heading and distance estimate of a `WallFollow` update
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct WallFollowState {
+    /// Distance (mm) from the wall square to it, or `None` if the wall is lost
+    pub distance: Option<f32>,
+    /// Heading to hold, increasing to the left
+    pub heading: Angle<Radians>,
+}
+
+/// Drives parallel to a straight wall at a set distance, using a side range sensor and the
+/// IMU yaw. The wall's heading is given up front, and the robot steers toward or away from
+/// the wall in proportion to the distance error. While the wall is lost, the robot holds the
+/// wall's heading.
+#[derive(Debug, Copy, Clone)]
+pub struct WallFollow {
+    config: WallFollowConfig,
+    /// Heading (rad) along the wall, increasing to the left
+    wall_heading: f32,
+    /// Latest range (mm) from the side sensor, `None` if it measured nothing
+    range: Option<f32>,
+}
+
+impl WallFollow {
+    /// Follow a wall running along `wall_heading`, in the IMU's yaw frame
+    pub fn new(config: WallFollowConfig, wall_heading: Angle<Radians>) -> Self {
+        Self {
+            config,
+            wall_heading: wrap(wall_heading.value()),
+            range: None,
+        }
+    }
+
+    pub fn config(&self) -> WallFollowConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: WallFollowConfig) {
+        self.config = config;
+    }
+
+    pub fn wall_heading(&self) -> Angle<Radians> {
+        Angle::new(self.wall_heading)
+    }
+
+    pub fn set_wall_heading(&mut self, wall_heading: Angle<Radians>) {
+        self.wall_heading = wrap(wall_heading.value());
+    }
+
+    /// Latest range (mm) recorded with `set_range`
+    pub fn range(&self) -> Option<f32> {
+        self.range
+    }
+
+    /// Record the latest range (mm) from the side sensor, `None` if it measured nothing.
+    /// Held until the next measurement finishes.
+    pub fn set_range(&mut self, range: Option<f32>) {
+        self.range = range;
+    }
+
+    /// Returns the heading to hold for a `range` (mm) from the side sensor, if it measured
+    /// anything, and the current `yaw`, which must increase when turning left.
+    pub fn update(&self, range: Option<f32>, yaw: Angle<Radians>) -> WallFollowState {
+        // Angled to the wall, the beam hits it further along than the square distance
+        let angle = wrap(yaw.value() - self.wall_heading);
+        let distance = range
+            .filter(|range| *range <= self.config.max_range)
+            .map(|range| range * libm::cosf(angle));
+
+        let correction = distance.map_or(0.0, |distance| {
+            let max = self.config.max_angle.value();
+            (self.config.kp * (distance - self.config.distance)).clamp(-max, max)
+        });
+        // Too far steers toward the wall
+        let correction = match self.config.side {
+            Side::Left => correction,
+            Side::Right => -correction,
+        };
+
+        WallFollowState {
+            distance,
+            heading: Angle::new(wrap(self.wall_heading + correction)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Forward speed (mm/s)
+    const SPEED: f32 = 100.0;
+    const DT: f32 = 0.02;
+
+    /// Drives along a straight wall at heading zero for `time` (s), starting `distance` (mm)
+    /// from it and parallel to it. The robot turns toward the held heading with a lag, as
+    /// the heading controller would. Returns the final distance and heading.
+    fn follow(side: Side, distance: f32, time: f32) -> (f32, f32) {
+        let wall = WallFollow::new(
+            WallFollowConfig {
+                side,
+                ..WallFollowConfig::default()
+            },
+            Angle::new(0.0),
+        );
+        let mut distance = distance;
+        let mut yaw: f32 = 0.0;
+        for _ in 0..(time / DT) as usize {
+            // The sensor is square to the robot, so an angled robot sees a longer range
+            let range = distance / libm::cosf(yaw);
+            let state = wall.update(Some(range), Angle::new(yaw));
+            yaw += (state.heading.value() - yaw) * 5.0 * DT;
+
+            // Turning left moves away from a wall on the right
+            let sideways = SPEED * libm::sinf(yaw) * DT;
+            distance += match side {
+                Side::Left => -sideways,
+                Side::Right => sideways,
+            };
+        }
+        (distance, yaw)
+    }
+
+    #[test]
+    fn converges_on_right_wall() {
+        for start in [80.0, 300.0] {
+            let (distance, yaw) = follow(Side::Right, start, 30.0);
+            assert!(
+                (distance - 150.0).abs() < 2.0,
+                "{start} ended at {distance}"
+            );
+            assert!(yaw.abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn converges_on_left_wall() {
+        for start in [80.0, 300.0] {
+            let (distance, yaw) = follow(Side::Left, start, 30.0);
+            assert!(
+                (distance - 150.0).abs() < 2.0,
+                "{start} ended at {distance}"
+            );
+            assert!(yaw.abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn steers_toward_distant_wall() {
+        let wall = WallFollow::new(WallFollowConfig::default(), Angle::new(0.0));
+        // Too far from a wall on the right turns right, up to the largest correction
+        let state = wall.update(Some(400.0), Angle::new(0.0));
+        assert_eq!(state.distance, Some(400.0));
+        assert_eq!(state.heading.value(), -wall.config().max_angle.value());
+    }
+
+    #[test]
+    fn holds_wall_heading_while_lost() {
+        let wall = WallFollow::new(WallFollowConfig::default(), Angle::new(1.0));
+        for range in [None, Some(600.0)] {
+            let state = wall.update(range, Angle::new(0.8));
+            assert_eq!(state.distance, None);
+            assert_eq!(state.heading.value(), 1.0);
+        }
+    }
+
+    #[test]
+    fn corrects_range_for_angle_to_wall() {
+        let wall = WallFollow::new(WallFollowConfig::default(), Angle::new(0.0));
+        let state = wall.update(Some(200.0), Angle::new(consts::PI / 3.0));
+        assert!((state.distance.unwrap() - 100.0).abs() < 1e-3);
+    }
+}